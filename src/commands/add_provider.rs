@@ -6,16 +6,22 @@ use std::path::Path;
 /// Add a given ProviderSource to our configuration file.
 pub fn add_provider_to_config(
     workspace: &Path,
-    provider_source: ProviderSource,
+    mut provider_source: ProviderSource,
     file: &Path,
 ) -> anyhow::Result<()> {
+    provider_source.set_workspace(workspace);
     if !provider_source.correctly_configured() {
         return Err(anyhow!("Provider is not correctly configured"));
     }
     let path_to_config = workspace.join(file);
     // Load and parse our configuration files
     let config = Config::new(vec![path_to_config]);
-    let mut sources = config.read().with_context(|| "Error reading config file")?;
+    let mut sources = config
+        .read_as_written()
+        .with_context(|| "Error reading config file")?;
+    for source in &mut sources {
+        source.set_workspace(workspace);
+    }
     // Ensure we don't add duplicates:
     if sources.iter().any(|s| s == &provider_source) {
         println!("Entry already exists, skipping");
@@ -7,9 +7,11 @@ use std::path::{Path, PathBuf};
 
 use super::get_all_repositories_to_archive;
 
-pub fn archive(workspace: &Path, force: bool) -> anyhow::Result<()> {
+pub fn archive(workspace: &Path, force: bool, offline: bool) -> anyhow::Result<()> {
     // Archive any repositories that have been deleted from the lockfile.
-    lock(workspace)?;
+    if !offline {
+        lock(workspace, false)?;
+    }
 
     let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
     let repositories = lockfile.read().context("Error reading lockfile")?;
@@ -2,7 +2,7 @@ use super::execute_cmd;
 use std::path::Path;
 
 /// Run `git fetch` on all our repositories
-pub fn fetch(workspace: &Path, threads: usize) -> anyhow::Result<()> {
+pub fn fetch(workspace: &Path, threads: usize, provider: Option<&str>) -> anyhow::Result<()> {
     let cmd = [
         "fetch",
         "--all",
@@ -13,6 +13,7 @@ pub fn fetch(workspace: &Path, threads: usize) -> anyhow::Result<()> {
     execute_cmd(
         workspace,
         threads,
+        provider,
         "git".to_string(),
         cmd.iter().map(|s| (*s).to_string()).collect(),
     )?;
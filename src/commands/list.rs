@@ -1,18 +1,36 @@
+use super::filter_by_provider;
 use crate::lockfile::Lockfile;
 use anyhow::Context;
 use std::path::Path;
 
-/// List the contents of our workspace
-pub fn list(workspace: &Path, full: bool) -> anyhow::Result<()> {
+/// List the contents of our workspace. With `show_provider`, the provider that found each
+/// repository and its identifier there are printed after its name, separated by tabs.
+pub fn list(
+    workspace: &Path,
+    full: bool,
+    provider: Option<&str>,
+    show_provider: bool,
+) -> anyhow::Result<()> {
     // Read and parse the lockfile
     let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
     let repositories = lockfile.read().context("Error reading lockfile")?;
+    let repositories = filter_by_provider(repositories, provider);
     let existing_repositories = repositories.iter().filter(|r| r.exists(workspace));
     for repo in existing_repositories {
-        if full {
-            println!("{}", repo.get_path(workspace).unwrap().display());
+        let name = if full {
+            repo.get_path(workspace).unwrap().display().to_string()
         } else {
-            println!("{}", repo.name());
+            repo.name().to_string()
+        };
+        if show_provider {
+            println!(
+                "{}\t{}\t{}",
+                name,
+                repo.provider.as_deref().unwrap_or("-"),
+                repo.id.as_deref().unwrap_or("-")
+            );
+        } else {
+            println!("{}", name);
         }
     }
     Ok(())
@@ -1,21 +1,34 @@
-use crate::config::Config;
+use crate::config::{Config, ProviderSource};
 use crate::lockfile::Lockfile;
+use crate::providers::ResponseCache;
 use crate::repository::Repository;
 use anyhow::Context;
+use console::style;
 use indicatif::ParallelProgressIterator;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 use std::path::Path;
+use std::time::SystemTime;
 
-/// Update our lockfile
-pub fn lock(workspace: &Path) -> anyhow::Result<()> {
+/// Update our lockfile. With `keep_going`, the repositories of providers that fail are kept
+/// from the previous lockfile instead of aborting.
+pub fn lock(workspace: &Path, keep_going: bool) -> anyhow::Result<()> {
     let config = Config::from_workspace(workspace)?;
 
     // Read the configuration sources
-    let sources = config
+    let mut sources = config
         .read()
         .with_context(|| "Error reading config files")?;
 
+    // Unchanged API responses are revalidated instead of fetched again
+    let cache_dir = workspace.join(".git-workspace").join("cache");
+    for source in &mut sources {
+        source.set_workspace(workspace);
+        if let Some(http) = source.provider_mut().http_options_mut() {
+            http.cache_dir = Some(cache_dir.clone());
+        }
+    }
+
     let total_bar = ProgressBar::new(sources.len() as u64);
     total_bar.set_style(
         ProgressStyle::default_bar()
@@ -24,9 +37,10 @@ pub fn lock(workspace: &Path) -> anyhow::Result<()> {
     );
 
     println!("Fetching repositories...");
+    let started = SystemTime::now();
 
     // For each source, in sequence, fetch the repositories
-    let results = sources
+    let results: Vec<_> = sources
         .par_iter()
         .map(|source| {
             source
@@ -34,14 +48,204 @@ pub fn lock(workspace: &Path) -> anyhow::Result<()> {
                 .with_context(|| format!("Error fetching repositories from {}", source))
         })
         .progress_with(total_bar)
-        .collect::<anyhow::Result<Vec<_>>>()?;
-    let mut all_repositories: Vec<Repository> = results.into_iter().flatten().collect();
-    // let all_repositories: Vec<Repository> = all_repository_results.iter().collect::<anyhow::Result<Vec<Repository>>>()?;
-    // We may have duplicated repositories here. Make sure they are unique based on the full path.
-    all_repositories.sort();
-    all_repositories.dedup();
-    // Write the lockfile out
+        .collect();
+
+    // A provider that failed may not have used all of its cached responses
+    if results.iter().all(Result::is_ok) {
+        ResponseCache::prune(&cache_dir, started);
+    }
+
     let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
+    // The previous lockfile is used to keep the repositories of failing providers and to report
+    // renames. An unreadable one is an error, otherwise their repositories would be archived.
+    let previous = if lockfile.exists() {
+        lockfile.read().context("Error reading lockfile")?
+    } else {
+        vec![]
+    };
+    let all_repositories = if keep_going {
+        keep_failed_providers(&previous, &sources, results)
+    } else {
+        results
+            .into_iter()
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect()
+    };
+    let all_repositories = unique_repositories(all_repositories);
+
+    let renamed = renamed_repositories(&previous, &all_repositories);
+    if !renamed.is_empty() {
+        println!("Repositories renamed since the last lock:");
+        for (before, after) in renamed {
+            println!(
+                "  {} is now {}",
+                style(before.name()).yellow(),
+                style(after.name()).green()
+            );
+        }
+    }
+
+    // Write the lockfile out
     lockfile.write(&all_repositories)?;
     Ok(())
 }
+
+/// Sort the repositories and keep one per path. Several providers may find the same repository,
+/// e.g. an organization and the starred repositories of a user.
+fn unique_repositories(mut repositories: Vec<Repository>) -> Vec<Repository> {
+    repositories.sort();
+    repositories.dedup_by(|a, b| a.name() == b.name());
+    repositories
+}
+
+/// Collect the repositories of the providers that succeeded, and the previously locked
+/// repositories of the ones that failed, marked as stale. Repositories locked before providers
+/// were recorded cannot be kept.
+fn keep_failed_providers(
+    previous: &[Repository],
+    sources: &[ProviderSource],
+    results: Vec<anyhow::Result<Vec<Repository>>>,
+) -> Vec<Repository> {
+    let mut repositories = vec![];
+    let mut stale = vec![];
+    let mut failures = vec![];
+    for (source, result) in sources.iter().zip(results) {
+        match result {
+            Ok(fetched) => repositories.extend(fetched),
+            Err(error) => {
+                let id = source.id();
+                let kept: Vec<_> = previous
+                    .iter()
+                    .filter(|r| r.provider.as_ref() == Some(&id))
+                    .cloned()
+                    .map(|mut r| {
+                        r.stale = true;
+                        r
+                    })
+                    .collect();
+                failures.push((source, error, kept.len()));
+                stale.extend(kept);
+            }
+        }
+    }
+    // A provider that succeeded may have found the same repositories
+    stale.retain(|r| {
+        !repositories
+            .iter()
+            .any(|fetched| fetched.name() == r.name())
+    });
+    repositories.extend(stale);
+
+    if !failures.is_empty() {
+        println!(
+            "{}",
+            style(format!(
+                "Failed to fetch repositories from {} of {} providers:",
+                failures.len(),
+                sources.len()
+            ))
+            .red()
+        );
+        for (source, error, kept) in failures {
+            println!("  {}", source);
+            println!("    {}", style(format!("{:#}", error)).red());
+            println!("    Kept {} repositories from the previous lockfile", kept);
+        }
+    }
+    repositories
+}
+
+/// Pairs of previously locked repositories and the ones with the same provider and identifier
+/// that have another name now
+fn renamed_repositories<'a>(
+    previous: &'a [Repository],
+    current: &'a [Repository],
+) -> Vec<(&'a Repository, &'a Repository)> {
+    previous
+        .iter()
+        .filter(|before| before.provider.is_some() && before.id.is_some())
+        .filter_map(|before| {
+            current
+                .iter()
+                .find(|after| after.provider == before.provider && after.id == before.id)
+                .filter(|after| after.name() != before.name())
+                .map(|after| (before, after))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::{LocalProvider, ManualProvider};
+
+    fn locked(path: &str, provider: &str) -> Repository {
+        let mut repository = Repository::new(path.to_string(), String::new(), None, None);
+        repository.provider = Some(provider.to_string());
+        repository
+    }
+
+    #[test]
+    fn test_keep_failed_providers() {
+        let mut local = LocalProvider::default();
+        local.name = "repositories".into();
+        let local = ProviderSource::Local(local);
+        let manual = ProviderSource::Manual(ManualProvider::default());
+        let previous = [
+            locked("local/api", "local:repositories"),
+            locked("local/web", "local:repositories"),
+            locked("vendor/libfoo", "manual"),
+        ];
+
+        let repositories = keep_failed_providers(
+            &previous,
+            &[local, manual],
+            vec![
+                Err(anyhow::anyhow!("Invalid token")),
+                Ok(vec![locked("vendor/libbar", "manual")]),
+            ],
+        );
+
+        let mut expected = vec![
+            locked("vendor/libbar", "manual"),
+            locked("local/api", "local:repositories"),
+            locked("local/web", "local:repositories"),
+        ];
+        expected[1].stale = true;
+        expected[2].stale = true;
+        assert_eq!(repositories, expected);
+    }
+
+    #[test]
+    fn test_unique_repositories() {
+        let repositories = unique_repositories(vec![
+            locked("github/acme/web", "github:acme"),
+            locked("github/acme/api", "github:someone/stars"),
+            locked("github/acme/api", "github:acme"),
+        ]);
+        let names: Vec<_> = repositories.iter().map(|r| r.name().as_str()).collect();
+        assert_eq!(names, ["github/acme/api", "github/acme/web"]);
+    }
+
+    #[test]
+    fn test_renamed_repositories() {
+        let previous = [
+            locked("github/acme/api", "github:acme").with_id("R_1"),
+            locked("github/acme/web", "github:acme").with_id("R_2"),
+            locked("github/acme/old", "github:acme"),
+        ];
+        let current = [
+            locked("github/acme/api", "github:acme").with_id("R_1"),
+            locked("github/acme/website", "github:acme").with_id("R_2"),
+            locked("github/acme/new", "github:acme"),
+            locked("gitlab/acme/web", "gitlab:acme").with_id("R_2"),
+        ];
+        let renamed: Vec<_> = renamed_repositories(&previous, &current)
+            .into_iter()
+            .map(|(before, after)| (before.name().as_str(), after.name().as_str()))
+            .collect();
+        assert_eq!(renamed, vec![("github/acme/web", "github/acme/website")]);
+    }
+}
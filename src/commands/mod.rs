@@ -27,6 +27,25 @@ use std::sync::Arc;
 use std::time::Duration;
 use walkdir::WalkDir;
 
+/// Keep the repositories found by a provider, given by its identifier like `github:my-org`, or
+/// by its kind like `github`. All repositories are kept if no provider is given.
+pub fn filter_by_provider(
+    repositories: Vec<Repository>,
+    provider: Option<&str>,
+) -> Vec<Repository> {
+    let provider = match provider {
+        Some(provider) => provider,
+        None => return repositories,
+    };
+    repositories
+        .into_iter()
+        .filter(|r| match &r.provider {
+            Some(id) => id == provider || id.starts_with(&format!("{}:", provider)),
+            None => false,
+        })
+        .collect()
+}
+
 /// Take any number of repositories and apply `f` on each one.
 /// This method takes care of displaying progress bars and displaying
 /// any errors that may arise.
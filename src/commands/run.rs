@@ -1,4 +1,4 @@
-use super::map_repositories;
+use super::{filter_by_provider, map_repositories};
 use crate::lockfile::Lockfile;
 use crate::repository::Repository;
 use std::path::Path;
@@ -7,12 +7,13 @@ use std::path::Path;
 pub fn execute_cmd(
     workspace: &Path,
     threads: usize,
+    provider: Option<&str>,
     cmd: String,
     args: Vec<String>,
 ) -> anyhow::Result<()> {
     // Read the lockfile
     let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
-    let repositories = lockfile.read()?;
+    let repositories = filter_by_provider(lockfile.read()?, provider);
 
     // We only care about repositories that exist
     let repos_to_fetch: Vec<Repository> = repositories
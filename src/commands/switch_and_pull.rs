@@ -1,11 +1,16 @@
-use super::map_repositories;
+use super::{filter_by_provider, map_repositories};
 use crate::lockfile::Lockfile;
 use anyhow::Context;
 use std::path::Path;
 
-pub fn pull_all_repositories(workspace: &Path, threads: usize) -> anyhow::Result<()> {
+pub fn pull_all_repositories(
+    workspace: &Path,
+    threads: usize,
+    provider: Option<&str>,
+) -> anyhow::Result<()> {
     let lockfile = Lockfile::new(workspace.join("workspace-lock.toml"));
     let repositories = lockfile.read().with_context(|| "Error reading lockfile")?;
+    let repositories = filter_by_provider(repositories, provider);
 
     println!(
         "Switching to the primary branch and pulling {} repositories",
@@ -1,13 +1,21 @@
-use crate::providers::{GiteaProvider, GithubProvider, GitlabProvider, Provider};
+use crate::providers::{
+    AzureDevopsProvider, BitbucketProvider, BitbucketServerProvider, CommandProvider,
+    ConnectionOptions, ForgejoProvider, GerritProvider, GiteaProvider, GithubProvider,
+    GitlabProvider, LocalProvider, ManualProvider, Provider, SourcehutProvider,
+};
 use crate::repository::Repository;
 use anyhow::Context;
+use console::style;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Default)]
 struct ConfigContents {
+    /// Defaults for the providers of the file
+    #[serde(default, skip_serializing_if = "ConnectionOptions::is_empty")]
+    http: ConnectionOptions,
     #[serde(rename = "provider", default)]
     providers: Vec<ProviderSource>,
 }
@@ -52,6 +60,14 @@ impl Config {
         Ok(Self::new(config_files))
     }
 
+    fn read_file(path: &Path) -> anyhow::Result<ConfigContents> {
+        let file_contents = fs::read_to_string(path)
+            .with_context(|| format!("Cannot read file {}", path.display()))?;
+        toml::from_str(file_contents.as_str())
+            .with_context(|| format!("Error parsing TOML in file {}", path.display()))
+    }
+
+    /// Read the providers, with the `[http]` table of each file applied to its providers
     pub fn read(&self) -> anyhow::Result<Vec<ProviderSource>> {
         let mut all_providers = vec![];
 
@@ -59,16 +75,39 @@ impl Config {
             if !path.exists() {
                 continue;
             }
-            let file_contents = fs::read_to_string(path)
-                .with_context(|| format!("Cannot read file {}", path.display()))?;
-            let contents: ConfigContents = toml::from_str(file_contents.as_str())
-                .with_context(|| format!("Error parsing TOML in file {}", path.display()))?;
-            all_providers.extend(contents.providers);
+            let contents = Self::read_file(path)?;
+            all_providers.extend(contents.providers.into_iter().map(|mut source| {
+                if let Some(http) = source.provider_mut().http_options_mut() {
+                    http.connection.merge_defaults(&contents.http);
+                }
+                source
+            }));
+        }
+        Ok(all_providers)
+    }
+
+    /// Read the providers as they are written in the files, to write them back
+    pub fn read_as_written(&self) -> anyhow::Result<Vec<ProviderSource>> {
+        let mut all_providers = vec![];
+
+        for path in &self.files {
+            if !path.exists() {
+                continue;
+            }
+            all_providers.extend(Self::read_file(path)?.providers);
         }
         Ok(all_providers)
     }
+
+    /// Write the providers to a file, keeping its `[http]` table
     pub fn write(&self, providers: Vec<ProviderSource>, config_path: &Path) -> anyhow::Result<()> {
-        let toml = toml::to_string(&ConfigContents { providers })?;
+        let mut contents = if config_path.exists() {
+            Self::read_file(config_path)?
+        } else {
+            ConfigContents::default()
+        };
+        contents.providers = providers;
+        let toml = toml::to_string(&contents)?;
         fs::write(config_path, toml)
             .with_context(|| format!("Error writing to file {}", config_path.display()))?;
         Ok(())
@@ -80,26 +119,128 @@ impl Config {
 #[serde(rename_all = "lowercase")]
 #[derive(clap::Subcommand)]
 pub enum ProviderSource {
+    // Named like the subcommands, the names written by older versions are still accepted.
+    #[serde(rename = "azure-devops", alias = "azuredevops")]
+    AzureDevops(AzureDevopsProvider),
+    Bitbucket(BitbucketProvider),
+    #[serde(rename = "bitbucket-server", alias = "bitbucketserver")]
+    BitbucketServer(BitbucketServerProvider),
+    Forgejo(ForgejoProvider),
+    Gerrit(GerritProvider),
     Gitea(GiteaProvider),
     Gitlab(GitlabProvider),
     Github(GithubProvider),
+    Sourcehut(SourcehutProvider),
+    Command(CommandProvider),
+    Local(LocalProvider),
+    // Repositories are listed in the configuration file, there is nothing to add from the CLI.
+    #[command(skip)]
+    Manual(ManualProvider),
 }
 
 impl ProviderSource {
     pub fn provider(&self) -> &dyn Provider {
         match self {
+            Self::AzureDevops(config) => config,
+            Self::Bitbucket(config) => config,
+            Self::BitbucketServer(config) => config,
+            Self::Forgejo(config) => config,
+            Self::Gerrit(config) => config,
+            Self::Gitea(config) => config,
+            Self::Gitlab(config) => config,
+            Self::Github(config) => config,
+            Self::Sourcehut(config) => config,
+            Self::Command(config) => config,
+            Self::Local(config) => config,
+            Self::Manual(config) => config,
+        }
+    }
+
+    pub fn provider_mut(&mut self) -> &mut dyn Provider {
+        match self {
+            Self::AzureDevops(config) => config,
+            Self::Bitbucket(config) => config,
+            Self::BitbucketServer(config) => config,
+            Self::Forgejo(config) => config,
+            Self::Gerrit(config) => config,
             Self::Gitea(config) => config,
             Self::Gitlab(config) => config,
             Self::Github(config) => config,
+            Self::Sourcehut(config) => config,
+            Self::Command(config) => config,
+            Self::Local(config) => config,
+            Self::Manual(config) => config,
+        }
+    }
+
+    /// Sets the workspace directory, which relative paths of the configuration are relative to
+    pub fn set_workspace(&mut self, workspace: &Path) {
+        let provider = self.provider_mut();
+        provider.set_workspace(workspace);
+        if let Some(token) = provider.token_mut() {
+            token.workspace = workspace.to_path_buf();
+        }
+        if let Some(http) = provider.http_options_mut() {
+            http.workspace = workspace.to_path_buf();
         }
     }
 
     pub fn correctly_configured(&self) -> bool {
+        if let Err(e) = self.check_path_template() {
+            println!("{}", style(format!("Error: {:#}", e)).red());
+            return false;
+        }
         self.provider().correctly_configured()
     }
 
+    fn check_path_template(&self) -> anyhow::Result<()> {
+        match self.provider().path_template() {
+            Some(template) => template.check_archived(self.provider().archived()),
+            None => Ok(()),
+        }
+    }
+
+    /// The name of the provider, as written in the configuration file and on the command line
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::AzureDevops(_) => "azure-devops",
+            Self::Bitbucket(_) => "bitbucket",
+            Self::BitbucketServer(_) => "bitbucket-server",
+            Self::Forgejo(_) => "forgejo",
+            Self::Gerrit(_) => "gerrit",
+            Self::Gitea(_) => "gitea",
+            Self::Gitlab(_) => "gitlab",
+            Self::Github(_) => "github",
+            Self::Sourcehut(_) => "sourcehut",
+            Self::Command(_) => "command",
+            Self::Local(_) => "local",
+            Self::Manual(_) => "manual",
+        }
+    }
+
+    /// Identifies the provider in the lockfile, e.g. `github:my-org` or
+    /// `gitlab:gitlab.company.com/team`
+    pub fn id(&self) -> String {
+        let scope = self.provider().scope();
+        if scope.is_empty() {
+            self.kind().to_string()
+        } else {
+            format!("{}:{}", self.kind(), scope)
+        }
+    }
+
     pub fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
-        self.provider().fetch_repositories()
+        self.check_path_template()?;
+        let repositories = self.provider().fetch_repositories()?;
+        let mut repositories = match self.provider().path_template() {
+            Some(template) => template.apply(self.kind(), repositories)?,
+            None => repositories,
+        };
+        let id = self.id();
+        for repository in &mut repositories {
+            repository.provider = Some(id.clone());
+        }
+        Ok(repositories)
     }
 }
 
@@ -112,6 +253,7 @@ impl fmt::Display for ProviderSource {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::Visibility;
     use std::fs::File;
     use std::io::Write;
     use tempfile::TempDir;
@@ -198,6 +340,346 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_forgejo_defaults_to_codeberg() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        create_test_config(
+            dir_path,
+            "workspace.toml",
+            r#"[[provider]]
+            provider = "forgejo"
+            name = "forgejo-org"
+            path = "codeberg""#,
+        );
+
+        let config = Config::from_workspace(dir_path).unwrap();
+        let providers = config.read().unwrap();
+
+        match &providers[0] {
+            ProviderSource::Forgejo(config) => assert_eq!(config.url, "https://codeberg.org"),
+            _ => panic!("Expected Forgejo provider"),
+        }
+    }
+
+    #[test]
+    fn test_metadata_filter() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        create_test_config(
+            dir_path,
+            "workspace.toml",
+            r#"[[provider]]
+            provider = "gitea"
+            name = "gitea-org"
+            path = "gitea"
+            topics = ["backend"]
+            languages = ["rust", "go"]
+            visibility = ["private", "internal"]
+            pushed_within = "365d""#,
+        );
+
+        let config = Config::from_workspace(dir_path).unwrap();
+        let providers = config.read().unwrap();
+
+        match &providers[0] {
+            ProviderSource::Gitea(config) => {
+                assert_eq!(config.options.filter.topics, vec!["backend"]);
+                assert_eq!(config.options.filter.languages, vec!["rust", "go"]);
+                assert_eq!(
+                    config.options.filter.visibility,
+                    vec![Visibility::Private, Visibility::Internal]
+                );
+                assert_eq!(
+                    config.options.filter.pushed_within.map(|p| p.to_string()),
+                    Some("365d".to_string())
+                );
+            }
+            _ => panic!("Expected Gitea provider"),
+        }
+    }
+
+    #[test]
+    fn test_path_template() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        let repositories_dir = dir_path.join("repositories");
+        git2::Repository::init(repositories_dir.join("team-a/api")).unwrap();
+        git2::Repository::init(repositories_dir.join("team-b/Web")).unwrap();
+
+        create_test_config(
+            dir_path,
+            "workspace.toml",
+            &format!(
+                r#"[[provider]]
+                provider = "local"
+                name = '{}'
+                path = "local"
+                path_template = "{{provider}}/{{owner|flatten}}-{{name|lower}}""#,
+                repositories_dir.display()
+            ),
+        );
+
+        let config = Config::from_workspace(dir_path).unwrap();
+        let providers = config.read().unwrap();
+        let mut repositories = providers[0].fetch_repositories().unwrap();
+        repositories.sort();
+
+        let names: Vec<_> = repositories.iter().map(|r| r.name().clone()).collect();
+        let expected: Vec<_> = ["local/team-a-api", "local/team-b-web"]
+            .into_iter()
+            .map(|name| Repository::new(name.to_string(), String::new(), None, None))
+            .map(|r| r.name().clone())
+            .collect();
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn test_path_template_with_separate_archived() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        create_test_config(
+            dir_path,
+            "workspace.toml",
+            r#"[[provider]]
+            provider = "gitlab"
+            name = "acme"
+            path = "gitlab"
+            archived = "separate"
+            path_template = "{owner}/{name}""#,
+        );
+
+        // Without {path}, archived repositories would be cloned with the others
+        let providers = Config::from_workspace(dir_path).unwrap().read().unwrap();
+        assert!(!providers[0].correctly_configured());
+        let error = providers[0].fetch_repositories().unwrap_err();
+        assert!(error.to_string().contains("must contain {path}"));
+    }
+
+    #[test]
+    fn test_manual_provider() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        create_test_config(
+            dir_path,
+            "workspace.toml",
+            r#"[[provider]]
+            provider = "manual"
+            [[provider.repo]]
+            path = "vendor/libfoo"
+            url = "https://example.com/libfoo.git"
+            branch = "main"
+            [[provider.repo]]
+            path = "forks/bar"
+            url = "git@example.com:me/bar.git"
+            upstream = "git@example.com:them/bar.git""#,
+        );
+
+        let config = Config::from_workspace(dir_path).unwrap();
+        let providers = config.read().unwrap();
+        assert_eq!(providers.len(), 1);
+        assert!(providers[0].correctly_configured());
+
+        let repositories = providers[0].fetch_repositories().unwrap();
+        let mut expected = vec![
+            Repository::new(
+                "vendor/libfoo".to_string(),
+                "https://example.com/libfoo.git".to_string(),
+                Some("main".to_string()),
+                None,
+            ),
+            Repository::new(
+                "forks/bar".to_string(),
+                "git@example.com:me/bar.git".to_string(),
+                None,
+                Some("git@example.com:them/bar.git".to_string()),
+            ),
+        ];
+        for repository in &mut expected {
+            repository.provider = Some("manual".to_string());
+        }
+        assert_eq!(repositories, expected);
+    }
+
+    #[test]
+    fn test_http_defaults() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = create_test_config(
+            temp_dir.path(),
+            "workspace.toml",
+            r#"[http]
+            proxy = "http://proxy.company.com:3128"
+            ca_bundle = "~/company-ca.pem"
+
+            [[provider]]
+            provider = "gitlab"
+            name = "team"
+            url = "https://gitlab.company.com"
+            path = "gitlab"
+
+            [[provider]]
+            provider = "github"
+            name = "company"
+            path = "github"
+            proxy = "socks5://localhost:1080"
+
+            [[provider]]
+            provider = "local"
+            name = "."
+            path = "local""#,
+        );
+        let config = Config::new(vec![config_path.clone()]);
+
+        let mut providers = config.read().unwrap();
+        let connections: Vec<_> = providers
+            .iter_mut()
+            .map(|p| {
+                p.provider_mut()
+                    .http_options_mut()
+                    .map(|h| h.connection.clone())
+            })
+            .collect();
+        let connection = |proxy: &str| ConnectionOptions {
+            proxy: Some(proxy.to_string()),
+            ca_bundle: Some(PathBuf::from("~/company-ca.pem")),
+        };
+        assert_eq!(
+            connections,
+            vec![
+                Some(connection("http://proxy.company.com:3128")),
+                Some(connection("socks5://localhost:1080")),
+                None,
+            ]
+        );
+
+        // Adding a provider keeps the defaults in the [http] table
+        let mut providers = config.read_as_written().unwrap();
+        providers.push(ProviderSource::Sourcehut(SourcehutProvider::default()));
+        config.write(providers, &config_path).unwrap();
+        let content = fs::read_to_string(&config_path).unwrap();
+        assert!(content.starts_with("[http]"));
+        assert_eq!(content.matches("proxy.company.com").count(), 1);
+        assert_eq!(config.read().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_provider_names() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = create_test_config(
+            temp_dir.path(),
+            "workspace.toml",
+            r#"[[provider]]
+            provider = "azuredevops"
+            name = "org"
+            path = "azure"
+
+            [[provider]]
+            provider = "bitbucket-server"
+            name = "PROJ"
+            url = "https://bitbucket.company.com"
+            path = "bitbucket-server""#,
+        );
+        let config = Config::new(vec![config_path.clone()]);
+        let providers = config.read_as_written().unwrap();
+        let kinds: Vec<_> = providers.iter().map(|p| p.kind()).collect();
+        assert_eq!(kinds, ["azure-devops", "bitbucket-server"]);
+
+        // The names are the ones of the subcommands, and are written back that way
+        let add =
+            <ProviderSource as clap::Subcommand>::augment_subcommands(clap::Command::new("add"));
+        assert!(kinds.iter().all(|kind| add.find_subcommand(kind).is_some()));
+        config.write(providers, &config_path).unwrap();
+        let content = fs::read_to_string(&config_path).unwrap();
+        assert!(content.contains(r#"provider = "azure-devops""#));
+        assert!(content.contains(r#"provider = "bitbucket-server""#));
+    }
+
+    #[test]
+    fn test_provider_ids() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = create_test_config(
+            temp_dir.path(),
+            "workspace.toml",
+            r#"[[provider]]
+            provider = "github"
+            name = "acme"
+            path = "github"
+
+            [[provider]]
+            provider = "github"
+            name = "acme"
+            path = "github"
+            starred = true
+
+            [[provider]]
+            provider = "github"
+            name = "acme"
+            path = "github"
+            team = "backend"
+
+            [[provider]]
+            provider = "gitlab"
+            name = "acme"
+            path = "gitlab"
+
+            [[provider]]
+            provider = "gitlab"
+            name = "acme"
+            url = "https://gitlab.company.com/"
+            path = "gitlab"
+
+            [[provider]]
+            provider = "azure-devops"
+            name = "acme"
+            path = "azure"
+
+            [[provider]]
+            provider = "azure-devops"
+            name = "acme"
+            project = "Backend"
+            path = "azure"
+
+            [[provider]]
+            provider = "gerrit"
+            url = "https://review.example.com"
+            path = "gerrit"
+
+            [[provider]]
+            provider = "command"
+            command = "list-repos"
+            args = ["--team", "a b"]
+
+            [[provider]]
+            provider = "command"
+            command = "list-repos"
+            args = ["--team", "a", "b"]
+
+            [[provider]]
+            provider = "manual""#,
+        );
+        let providers = Config::new(vec![config_path]).read().unwrap();
+        let ids: Vec<_> = providers.iter().map(|p| p.id()).collect();
+        assert_eq!(
+            ids,
+            [
+                "github:acme",
+                "github:acme/stars",
+                "github:acme/teams/backend",
+                "gitlab:acme",
+                "gitlab:gitlab.company.com/acme",
+                "azure-devops:acme",
+                "azure-devops:acme/Backend",
+                "gerrit:review.example.com",
+                "command:list-repos --team \"a b\"",
+                "command:list-repos --team a b",
+                "manual",
+            ]
+        );
+    }
+
     #[test]
     fn test_config_write() {
         let temp_dir = TempDir::new().unwrap();
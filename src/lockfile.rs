@@ -19,6 +19,10 @@ impl Lockfile {
         Lockfile { path }
     }
 
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
     pub fn read(&self) -> anyhow::Result<Vec<Repository>> {
         let config_data = fs::read_to_string(&self.path)
             .with_context(|| format!("Cannot read file {}", self.path.display()))?;
@@ -11,6 +11,9 @@ use std::path::PathBuf;
 struct Args {
     #[arg(short = 'w', long = "workspace", env = "GIT_WORKSPACE")]
     workspace: PathBuf,
+    /// Don't fetch repositories from the providers, use the existing lockfile instead
+    #[arg(long = "offline", global = true)]
+    offline: bool,
     #[command(subcommand)]
     command: Command,
 }
@@ -21,18 +24,31 @@ enum Command {
     Update {
         #[arg(short = 't', long = "threads", default_value = "8")]
         threads: usize,
+        /// Keep the previously locked repositories of providers that fail, instead of aborting
+        #[arg(long = "keep-going")]
+        keep_going: bool,
     },
     /// Fetch new commits for all repositories in the workspace
     Fetch {
         #[arg(short = 't', long = "threads", default_value = "8")]
         threads: usize,
+        /// Only use the repositories of a provider, e.g. `github` or `github:my-org`
+        #[arg(long = "provider")]
+        provider: Option<String>,
     },
     /// Fetch all repositories from configured providers and write the lockfile
-    Lock {},
+    Lock {
+        /// Keep the previously locked repositories of providers that fail, instead of aborting
+        #[arg(long = "keep-going")]
+        keep_going: bool,
+    },
     /// Pull new commits on the primary branch for all repositories in the workspace
     SwitchAndPull {
         #[arg(short = 't', long = "threads", default_value = "8")]
         threads: usize,
+        /// Only use the repositories of a provider, e.g. `github` or `github:my-org`
+        #[arg(long = "provider")]
+        provider: Option<String>,
     },
     /// List all repositories in the workspace
     ///
@@ -41,6 +57,12 @@ enum Command {
     List {
         #[arg(long = "full")]
         full: bool,
+        /// Only use the repositories of a provider, e.g. `github` or `github:my-org`
+        #[arg(long = "provider")]
+        provider: Option<String>,
+        /// Also output the provider of each repository and its identifier there
+        #[arg(long = "show-provider")]
+        show_provider: bool,
     },
     /// Archive repositories that don't exist in the workspace anymore.
     Archive {
@@ -56,6 +78,9 @@ enum Command {
     Run {
         #[arg(short = 't', long = "threads", default_value = "8")]
         threads: usize,
+        /// Only use the repositories of a provider, e.g. `github` or `github:my-org`
+        #[arg(long = "provider")]
+        provider: Option<String>,
         #[arg(required = true)]
         command: String,
         args: Vec<String>,
@@ -65,7 +90,7 @@ enum Command {
         #[arg(long = "file", default_value = "workspace.toml")]
         file: PathBuf,
         #[command(subcommand)]
-        command: ProviderSource,
+        command: Box<ProviderSource>,
     },
 }
 
@@ -81,23 +106,40 @@ fn handle_main(args: Args) -> anyhow::Result<()> {
 
     // Run our sub command. Pretty self-explanatory.
     match args.command {
-        Command::List { full } => list(&workspace_path, full)?,
-        Command::Update { threads } => {
-            lock(&workspace_path)?;
+        Command::List {
+            full,
+            provider,
+            show_provider,
+        } => list(&workspace_path, full, provider.as_deref(), show_provider)?,
+        Command::Update {
+            threads,
+            keep_going,
+        } => {
+            if !args.offline {
+                lock(&workspace_path, keep_going)?;
+            }
             update(&workspace_path, threads)?
         }
-        Command::Lock {} => {
-            lock(&workspace_path)?;
+        Command::Lock { keep_going } => {
+            if args.offline {
+                anyhow::bail!("The lockfile cannot be updated offline");
+            }
+            lock(&workspace_path, keep_going)?;
         }
-        Command::Archive { force } => archive(&workspace_path, force)?,
-        Command::Fetch { threads } => fetch(&workspace_path, threads)?,
-        Command::Add { file, command } => add_provider_to_config(&workspace_path, command, &file)?,
+        Command::Archive { force } => archive(&workspace_path, force, args.offline)?,
+        Command::Fetch { threads, provider } => {
+            fetch(&workspace_path, threads, provider.as_deref())?
+        }
+        Command::Add { file, command } => add_provider_to_config(&workspace_path, *command, &file)?,
         Command::Run {
             threads,
+            provider,
             command,
             args,
-        } => execute_cmd(&workspace_path, threads, command, args)?,
-        Command::SwitchAndPull { threads } => pull_all_repositories(&workspace_path, threads)?,
+        } => execute_cmd(&workspace_path, threads, provider.as_deref(), command, args)?,
+        Command::SwitchAndPull { threads, provider } => {
+            pull_all_repositories(&workspace_path, threads, provider.as_deref())?
+        }
     };
     Ok(())
 }
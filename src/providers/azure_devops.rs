@@ -0,0 +1,368 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, instance_scope, HttpClient, HttpOptions,
+    PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use base64::{engine::general_purpose, Engine};
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// See https://learn.microsoft.com/en-us/rest/api/azure/devops/git/repositories/list
+#[derive(Deserialize, Debug)]
+struct AzureDevopsRepositories {
+    value: Vec<AzureDevopsRepository>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AzureDevopsRepository {
+    id: String,
+    name: String,
+    project: AzureDevopsProject,
+    default_branch: Option<String>,
+    remote_url: String,
+    ssh_url: String,
+    #[serde(default)]
+    is_disabled: bool,
+    #[serde(default)]
+    is_fork: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct AzureDevopsProject {
+    name: String,
+}
+
+fn default_env_var() -> String {
+    String::from("AZURE_DEVOPS_TOKEN")
+}
+
+static DEFAULT_AZURE_DEVOPS_URL: &str = "https://dev.azure.com";
+
+fn public_azure_devops_url() -> String {
+    DEFAULT_AZURE_DEVOPS_URL.to_string()
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add an Azure DevOps organization by name")]
+pub struct AzureDevopsProvider {
+    /// The name of the organization to add
+    pub name: String,
+
+    #[arg(long = "project")]
+    #[serde(default)]
+    /// Only clone repositories from this project
+    project: Option<String>,
+
+    #[arg(long = "path", default_value = "azure")]
+    /// Clone repositories to a specific base path
+    path: String,
+
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
+
+    #[arg(long = "env-name", short = 'e', default_value = "AZURE_DEVOPS_TOKEN")]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the personal access token
+    env_var: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    http: HttpOptions,
+
+    #[arg(long = "skip-forks")]
+    #[serde(default)]
+    /// Don't clone forked repositories
+    skip_forks: bool,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// includes the project name.
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// includes the project name.
+    exclude: Vec<String>,
+
+    #[arg(long = "url", default_value = DEFAULT_AZURE_DEVOPS_URL)]
+    #[serde(default = "public_azure_devops_url")]
+    /// Azure DevOps instance URL, if using Azure DevOps Server this should be
+    /// http(s)://HOSTNAME/tfs
+    pub url: String,
+}
+
+impl fmt::Display for AzureDevopsProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match &self.project {
+            Some(project) => format!("{}/{}", self.name, project),
+            None => self.name.clone(),
+        };
+        write!(
+            f,
+            "Azure DevOps organization {} at {} in directory {}, using the token from {}",
+            style(name.to_lowercase()).green(),
+            style(&self.url).green(),
+            style(&self.path).green(),
+            style(self.token.describe(&self.env_var)).green(),
+        )
+    }
+}
+
+impl Provider for AzureDevopsProvider {
+    fn correctly_configured(&self) -> bool {
+        if let Err(e) = self.token.resolve(&self.env_var, &self.url) {
+            println!("{}", style(format!("Error: {:#}", e)).red());
+            println!("Create a personal access token with the Code (Read) scope here:");
+            println!("{}/{}/_usersSettings/tokens", self.url, self.name);
+            println!(
+                "Then set a {} environment variable with the value",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.name.contains('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that organization names do not contain forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.token)
+    }
+
+    fn scope(&self) -> String {
+        let name = match &self.project {
+            Some(project) => format!("{}/{}", self.name, project),
+            None => self.name.clone(),
+        };
+        instance_scope(&self.url, DEFAULT_AZURE_DEVOPS_URL, &name)
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        let azure_token = self.token.resolve(&self.env_var, &self.url)?;
+        // Personal access tokens are sent as the password of a basic authentication header,
+        // with an empty username.
+        let auth_header = format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode(format!(":{}", azure_token))
+        );
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = HttpClient::new(&self.http, &self.url)?;
+
+        let url = match &self.project {
+            Some(project) => format!(
+                "{}/{}/{}/_apis/git/repositories?api-version=7.1",
+                self.url, self.name, project
+            ),
+            None => format!(
+                "{}/{}/_apis/git/repositories?api-version=7.1",
+                self.url, self.name
+            ),
+        };
+
+        // This endpoint is not paginated, all repositories are returned at once.
+        let response: AzureDevopsRepositories = client
+            .call(client.get(&url).set("Authorization", &auth_header))?
+            .into_json()?;
+
+        let repositories = response
+            .value
+            .into_iter()
+            // Disabled repositories cannot be cloned
+            .filter(|r| !r.is_disabled)
+            .filter(|r| !self.skip_forks || !r.is_fork)
+            .map(|r| (format!("{}/{}", r.project.name, r.name), r))
+            .filter(|(full_name, _)| include_regex_set.is_match(full_name))
+            .filter(|(full_name, _)| !exclude_regex_set.is_match(full_name))
+            .map(|(full_name, r)| {
+                Repository::from_full_name(
+                    &self.path,
+                    &full_name,
+                    if self.auth_http {
+                        r.remote_url
+                    } else {
+                        r.ssh_url
+                    },
+                    r.default_branch
+                        .map(|b| b.trim_start_matches("refs/heads/").to_string()),
+                    None,
+                )
+                .with_id(r.id)
+            })
+            .collect();
+
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Response, TestServer};
+
+    // Trimmed from a real response, with the fields that are not used
+    const REPOSITORIES: &str = r#"{
+        "value": [
+            {
+                "id": "5febef5a-833d-4e14-b9c0-14cb638f91e6",
+                "name": "api",
+                "url": "https://dev.azure.com/acme/_apis/git/repositories/5febef5a-833d-4e14-b9c0-14cb638f91e6",
+                "project": {"id": "6ce954b1-ce1f-45d1-b94d-e6bf2464ba2c", "name": "Backend"},
+                "defaultBranch": "refs/heads/main",
+                "size": 1024,
+                "remoteUrl": "https://acme@dev.azure.com/acme/Backend/_git/api",
+                "sshUrl": "git@ssh.dev.azure.com:v3/acme/Backend/api",
+                "webUrl": "https://dev.azure.com/acme/Backend/_git/api",
+                "isDisabled": false,
+                "isInMaintenance": false
+            },
+            {
+                "id": "2f3d611a-f012-4b39-b157-8db63f380226",
+                "name": "empty",
+                "project": {"id": "6ce954b1-ce1f-45d1-b94d-e6bf2464ba2c", "name": "Backend"},
+                "size": 0,
+                "remoteUrl": "https://acme@dev.azure.com/acme/Backend/_git/empty",
+                "sshUrl": "git@ssh.dev.azure.com:v3/acme/Backend/empty",
+                "isDisabled": false
+            },
+            {
+                "id": "0ab6c08b-5d26-4c5c-9b2e-8d53a1f2a8e0",
+                "name": "api-fork",
+                "project": {"id": "6ce954b1-ce1f-45d1-b94d-e6bf2464ba2c", "name": "Backend"},
+                "defaultBranch": "refs/heads/feature/x",
+                "remoteUrl": "https://acme@dev.azure.com/acme/Backend/_git/api-fork",
+                "sshUrl": "git@ssh.dev.azure.com:v3/acme/Backend/api-fork",
+                "isFork": true
+            },
+            {
+                "id": "c9f3e1d2-7b4a-4f0e-a5d6-3e2b1c0f9a87",
+                "name": "legacy",
+                "project": {"id": "6ce954b1-ce1f-45d1-b94d-e6bf2464ba2c", "name": "Backend"},
+                "remoteUrl": "https://acme@dev.azure.com/acme/Backend/_git/legacy",
+                "sshUrl": "git@ssh.dev.azure.com:v3/acme/Backend/legacy",
+                "isDisabled": true
+            }
+        ],
+        "count": 4
+    }"#;
+
+    fn provider(server: &TestServer) -> AzureDevopsProvider {
+        AzureDevopsProvider {
+            name: "acme".to_string(),
+            path: "azure".to_string(),
+            url: server.url.clone(),
+            token: TokenSource {
+                token_command: Some("echo secret".to_string()),
+                ..Default::default()
+            },
+            http: HttpOptions {
+                allow_insecure_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn server() -> TestServer {
+        TestServer::start(|request| match request.target.as_str() {
+            "/acme/_apis/git/repositories?api-version=7.1"
+            | "/acme/Backend/_apis/git/repositories?api-version=7.1" => {
+                Some(Response::json(REPOSITORIES))
+            }
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn test_fetch_repositories() {
+        let server = server();
+        let repositories = provider(&server).fetch_repositories().unwrap();
+
+        let summary: Vec<_> = repositories
+            .iter()
+            .map(|r| (r.name().as_str(), r.url(), r.branch.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "azure/Backend/api",
+                    "git@ssh.dev.azure.com:v3/acme/Backend/api",
+                    Some("main")
+                ),
+                (
+                    "azure/Backend/empty",
+                    "git@ssh.dev.azure.com:v3/acme/Backend/empty",
+                    None
+                ),
+                (
+                    "azure/Backend/api-fork",
+                    "git@ssh.dev.azure.com:v3/acme/Backend/api-fork",
+                    Some("feature/x")
+                ),
+            ]
+        );
+        assert_eq!(
+            repositories[0].id.as_deref(),
+            Some("5febef5a-833d-4e14-b9c0-14cb638f91e6")
+        );
+
+        // Personal access tokens are sent with an empty username
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].header("Authorization"),
+            Some(format!("Basic {}", general_purpose::STANDARD.encode(":secret")).as_str())
+        );
+    }
+
+    #[test]
+    fn test_fetch_project_repositories() {
+        let server = server();
+        let mut provider = provider(&server);
+        provider.project = Some("Backend".to_string());
+        provider.skip_forks = true;
+        provider.auth_http = true;
+        provider.exclude = vec!["empty$".to_string()];
+        let repositories = provider.fetch_repositories().unwrap();
+
+        let urls: Vec<_> = repositories.iter().map(|r| r.url()).collect();
+        assert_eq!(urls, ["https://acme@dev.azure.com/acme/Backend/_git/api"]);
+        assert_eq!(
+            server.requests()[0].target,
+            "/acme/Backend/_apis/git/repositories?api-version=7.1"
+        );
+    }
+}
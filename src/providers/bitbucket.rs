@@ -0,0 +1,442 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, instance_scope, HttpClient, HttpOptions,
+    PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::bail;
+use base64::{engine::general_purpose, Engine};
+use console::style;
+use regex::RegexSet;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// See https://developer.atlassian.com/cloud/bitbucket/rest/api-group-repositories/
+#[derive(Deserialize, Debug)]
+struct BitbucketPage {
+    values: Vec<BitbucketRepository>,
+    next: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketRepository {
+    uuid: String,
+    full_name: String,
+    mainbranch: Option<BitbucketBranch>,
+    links: BitbucketLinks,
+    parent: Option<BitbucketParent>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketBranch {
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketLinks {
+    #[serde(default)]
+    clone: Vec<BitbucketCloneLink>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketCloneLink {
+    name: String,
+    href: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketParent {}
+
+/// The scheme and authority of a URL, lowercased
+fn url_origin(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or_default();
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    format!("{}://{}", scheme, authority).to_ascii_lowercase()
+}
+
+fn default_env_var() -> String {
+    String::from("BITBUCKET_TOKEN")
+}
+
+static DEFAULT_BITBUCKET_URL: &str = "https://api.bitbucket.org/2.0";
+
+fn public_bitbucket_url() -> String {
+    DEFAULT_BITBUCKET_URL.to_string()
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add a Bitbucket Cloud workspace by name")]
+pub struct BitbucketProvider {
+    /// The name of the Bitbucket workspace to add
+    pub name: String,
+
+    #[arg(long = "path", default_value = "bitbucket")]
+    /// Clone repositories to a specific base path
+    path: String,
+
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
+
+    #[arg(long = "env-name", short = 'e', default_value = "BITBUCKET_TOKEN")]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the auth token. Use `username:app-password` to
+    /// authenticate with an app password instead of an access token.
+    env_var: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    http: HttpOptions,
+
+    #[arg(long = "project")]
+    #[serde(default)]
+    /// Only clone repositories from the project with this key
+    project: Option<String>,
+
+    #[arg(long = "skip-forks")]
+    #[serde(default)]
+    /// Don't clone forked repositories
+    skip_forks: bool,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// includes the workspace name.
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// includes the workspace name.
+    exclude: Vec<String>,
+
+    #[arg(long = "url", default_value = DEFAULT_BITBUCKET_URL)]
+    #[serde(default = "public_bitbucket_url")]
+    /// Bitbucket API URL
+    pub url: String,
+}
+
+impl fmt::Display for BitbucketProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Bitbucket workspace {} in directory {}, using the token from {}",
+            style(&self.name.to_lowercase()).green(),
+            style(&self.path).green(),
+            style(self.token.describe(&self.env_var)).green(),
+        )
+    }
+}
+
+impl BitbucketProvider {
+    fn parse_page(
+        &self,
+        page: BitbucketPage,
+        include_regex_set: &RegexSet,
+        exclude_regex_set: &RegexSet,
+    ) -> Vec<Repository> {
+        let clone_link_name = if self.auth_http { "https" } else { "ssh" };
+        page.values
+            .into_iter()
+            .filter(|r| !self.skip_forks || r.parent.is_none())
+            .filter(|r| include_regex_set.is_match(&r.full_name))
+            .filter(|r| !exclude_regex_set.is_match(&r.full_name))
+            .filter_map(|r| {
+                let url = r
+                    .links
+                    .clone
+                    .into_iter()
+                    .find(|link| link.name == clone_link_name)?
+                    .href;
+                Some(
+                    Repository::from_full_name(
+                        &self.path,
+                        &r.full_name,
+                        url,
+                        r.mainbranch.map(|b| b.name),
+                        None,
+                    )
+                    .with_id(r.uuid),
+                )
+            })
+            .collect()
+    }
+}
+
+impl Provider for BitbucketProvider {
+    fn correctly_configured(&self) -> bool {
+        if let Err(e) = self
+            .token
+            .resolve_username_password(&self.env_var, &self.url)
+        {
+            println!("{}", style(format!("Error: {:#}", e)).red());
+            println!(
+                "Create an access token in your Bitbucket workspace settings, or an app password here: {}",
+                style("https://bitbucket.org/account/settings/app-passwords/").green()
+            );
+            println!(
+                "Then set a {} environment variable with the value (use `username:app-password` for app passwords)",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.name.contains('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that workspace names do not contain forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.token)
+    }
+
+    fn scope(&self) -> String {
+        let name = match &self.project {
+            Some(project) => format!("{}/{}", self.name, project),
+            None => self.name.clone(),
+        };
+        instance_scope(&self.url, DEFAULT_BITBUCKET_URL, &name)
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        let bitbucket_token = self
+            .token
+            .resolve_username_password(&self.env_var, &self.url)?;
+
+        // App passwords are sent with basic authentication, access tokens as bearer tokens.
+        let auth_header = if bitbucket_token.contains(':') {
+            format!(
+                "Basic {}",
+                general_purpose::STANDARD.encode(bitbucket_token.as_bytes())
+            )
+        } else {
+            format!("Bearer {}", bitbucket_token)
+        };
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = HttpClient::new(&self.http, &self.url)?;
+
+        let mut repositories = Vec::new();
+
+        let mut request = client
+            .get(&format!("{}/repositories/{}", self.url, self.name))
+            .query("pagelen", "100");
+        if let Some(project) = &self.project {
+            request = request.query("q", &format!("project.key=\"{}\"", project));
+        }
+
+        loop {
+            let page: BitbucketPage = client
+                .call(request.set("Authorization", &auth_header))?
+                .into_json()?;
+            let next = page.next.clone();
+
+            repositories.extend(self.parse_page(page, &include_regex_set, &exclude_regex_set));
+
+            // Bitbucket returns the full URL of the next page, including all query parameters.
+            // Its origin is checked, so the credentials are never sent to another host.
+            match next {
+                Some(next) if url_origin(&next) == url_origin(&self.url) => {
+                    request = client.get(&next)
+                }
+                Some(next) => bail!(
+                    "Bitbucket returned a next page on another host than {}: {}",
+                    self.url,
+                    next
+                ),
+                None => break,
+            }
+        }
+
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Response, TestServer};
+
+    const PAGE: &str = r#"{
+        "pagelen": 2,
+        "values": [
+            {
+                "uuid": "{2c3f3b8e-5d1c-4e36-9a0e-3b7c2f1d9a01}",
+                "full_name": "acme/api",
+                "mainbranch": {"name": "main", "type": "branch"},
+                "links": {
+                    "clone": [
+                        {"name": "https", "href": "https://bitbucket.org/acme/api.git"},
+                        {"name": "ssh", "href": "git@bitbucket.org:acme/api.git"}
+                    ]
+                }
+            },
+            {
+                "uuid": "{7f6e1c2a-9b4d-4a8e-b1c3-5d2e8f0a6b02}",
+                "full_name": "acme/api-fork",
+                "mainbranch": null,
+                "links": {
+                    "clone": [
+                        {"name": "https", "href": "https://bitbucket.org/acme/api-fork.git"},
+                        {"name": "ssh", "href": "git@bitbucket.org:acme/api-fork.git"}
+                    ]
+                },
+                "parent": {"full_name": "other/api", "type": "repository"}
+            }
+        ],
+        "next": "https://api.bitbucket.org/2.0/repositories/acme?pagelen=2&page=2"
+    }"#;
+
+    fn provider(skip_forks: bool, exclude: Vec<String>) -> BitbucketProvider {
+        BitbucketProvider {
+            name: "acme".to_string(),
+            path: "bitbucket".to_string(),
+            skip_forks,
+            exclude,
+            ..Default::default()
+        }
+    }
+
+    fn parse(provider: &BitbucketProvider) -> Vec<Repository> {
+        let page: BitbucketPage = serde_json::from_str(PAGE).unwrap();
+        assert!(page.next.is_some());
+        provider.parse_page(
+            page,
+            &create_include_regex_set(&provider.include).unwrap(),
+            &create_exclude_regex_set(&provider.exclude).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_parse_page() {
+        let repositories = parse(&provider(false, vec![]));
+        assert_eq!(repositories.len(), 2);
+        assert_eq!(repositories[0].name(), "bitbucket/acme/api");
+        assert_eq!(repositories[0].branch, Some("main".to_string()));
+        assert_eq!(
+            repositories[0].id.as_deref(),
+            Some("{2c3f3b8e-5d1c-4e36-9a0e-3b7c2f1d9a01}")
+        );
+        assert_eq!(repositories[1].name(), "bitbucket/acme/api-fork");
+        assert_eq!(repositories[1].branch, None);
+    }
+
+    #[test]
+    fn test_parse_page_filters() {
+        let repositories = parse(&provider(true, vec![]));
+        assert_eq!(repositories.len(), 1);
+        assert_eq!(repositories[0].name(), "bitbucket/acme/api");
+
+        let repositories = parse(&provider(false, vec!["api$".to_string()]));
+        assert_eq!(repositories.len(), 1);
+        assert_eq!(repositories[0].name(), "bitbucket/acme/api-fork");
+
+        let mut include = provider(false, vec![]);
+        include.include = vec!["fork".to_string()];
+        let repositories = parse(&include);
+        assert_eq!(repositories.len(), 1);
+        assert_eq!(repositories[0].name(), "bitbucket/acme/api-fork");
+    }
+
+    #[test]
+    fn test_fetch_repositories_pages() {
+        let server = TestServer::start(|request| {
+            let mut page: serde_json::Value = serde_json::from_str(PAGE).unwrap();
+            match request.target.as_str() {
+                "/repositories/acme?pagelen=100" => {
+                    // The next link is absolute and carries its own query parameters
+                    page["next"] = format!(
+                        "http://{}/repositories/acme?pagelen=100&page=2",
+                        request.header("Host")?
+                    )
+                    .into();
+                }
+                "/repositories/acme?pagelen=100&page=2" => {
+                    page["values"][0]["full_name"] = "acme/web".into();
+                    page["values"][1]["full_name"] = "acme/web-fork".into();
+                    page["next"] = serde_json::Value::Null;
+                }
+                _ => return None,
+            }
+            Some(Response::json(page))
+        });
+
+        let mut provider = provider(true, vec![]);
+        provider.url = server.url.clone();
+        provider.http.allow_insecure_http = true;
+        provider.token.token_command = Some("echo user:app-password".to_string());
+        let repositories = provider.fetch_repositories().unwrap();
+
+        let names: Vec<_> = repositories.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["bitbucket/acme/api", "bitbucket/acme/web"]);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.method == "GET"
+            && request.header("Authorization") == Some("Basic dXNlcjphcHAtcGFzc3dvcmQ=")));
+    }
+
+    #[test]
+    fn test_url_origin() {
+        assert_eq!(
+            url_origin("HTTPS://API.Bitbucket.org/2.0/repositories?page=2"),
+            "https://api.bitbucket.org"
+        );
+        assert_eq!(
+            url_origin("http://localhost:8080?page=2"),
+            "http://localhost:8080"
+        );
+        assert_ne!(
+            url_origin("https://api.bitbucket.org.evil.example/2.0"),
+            url_origin("https://api.bitbucket.org/2.0")
+        );
+    }
+
+    #[test]
+    fn test_fetch_repositories_next_on_other_host() {
+        let server = TestServer::start(|request| {
+            let mut page: serde_json::Value = serde_json::from_str(PAGE).unwrap();
+            match request.target.as_str() {
+                "/repositories/acme?pagelen=100" => {
+                    // Another port of the same host is another origin
+                    page["next"] = "http://127.0.0.1:1/repositories/acme?page=2".into();
+                }
+                _ => return None,
+            }
+            Some(Response::json(page))
+        });
+
+        let mut provider = provider(true, vec![]);
+        provider.url = server.url.clone();
+        provider.http.allow_insecure_http = true;
+        provider.token.token_command = Some("echo user:app-password".to_string());
+        let error = provider.fetch_repositories().unwrap_err();
+
+        assert!(error.to_string().contains("another host"), "{}", error);
+        assert_eq!(server.requests().len(), 1);
+    }
+}
@@ -0,0 +1,426 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, url_scope, Archived, HttpClient,
+    HttpOptions, PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::Context;
+use console::style;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// See https://developer.atlassian.com/server/bitbucket/rest/
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BitbucketServerPage {
+    values: Vec<BitbucketServerRepository>,
+    is_last_page: bool,
+    next_page_start: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerRepository {
+    id: u64,
+    slug: String,
+    project: BitbucketServerProject,
+    links: BitbucketServerLinks,
+    #[serde(default)]
+    archived: bool,
+    origin: Option<BitbucketServerOrigin>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerProject {
+    key: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct BitbucketServerLinks {
+    #[serde(default)]
+    clone: Vec<BitbucketServerCloneLink>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerCloneLink {
+    name: String,
+    href: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerOrigin {
+    #[serde(default)]
+    links: BitbucketServerLinks,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct BitbucketServerBranch {
+    display_id: String,
+}
+
+impl BitbucketServerLinks {
+    /// Find the clone URL of the `ssh` or `http` link
+    fn clone_url(&self, auth_http: bool) -> Option<String> {
+        let name = if auth_http { "http" } else { "ssh" };
+        self.clone
+            .iter()
+            .find(|link| link.name == name)
+            .map(|link| link.href.clone())
+    }
+}
+
+fn default_env_var() -> String {
+    String::from("BITBUCKET_SERVER_TOKEN")
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add a Bitbucket Server or Data Center project by key")]
+pub struct BitbucketServerProvider {
+    /// The key of the project to add. Personal projects are prefixed with a tilde, e.g. `~USER`
+    pub name: String,
+
+    #[arg(long = "url")]
+    /// Bitbucket Server instance URL
+    pub url: String,
+
+    #[arg(long = "path", default_value = "bitbucket-server")]
+    /// Clone repositories to a specific base path
+    path: String,
+
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
+
+    #[arg(
+        long = "env-name",
+        short = 'e',
+        default_value = "BITBUCKET_SERVER_TOKEN"
+    )]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the HTTP access token
+    env_var: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    http: HttpOptions,
+
+    #[arg(long = "skip-forks")]
+    #[serde(default)]
+    /// Don't clone forked repositories
+    skip_forks: bool,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// includes the project key.
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// includes the project key.
+    exclude: Vec<String>,
+
+    #[arg(long = "archived", value_enum, default_value_t)]
+    #[serde(default)]
+    /// Skip archived repositories, include them, or clone them to a separate `<path>-archived`
+    /// directory
+    archived: Archived,
+}
+
+impl fmt::Display for BitbucketServerProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Bitbucket Server project {} at {} in directory {}, using the token from {}",
+            style(&self.name.to_lowercase()).green(),
+            style(&self.url).green(),
+            style(&self.path).green(),
+            style(self.token.describe(&self.env_var)).green(),
+        )
+    }
+}
+
+impl BitbucketServerProvider {
+    /// Fetch the default branch of a repository, if it has one. Empty repositories don't.
+    fn fetch_default_branch(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        repo: &BitbucketServerRepository,
+    ) -> anyhow::Result<Option<String>> {
+        let repo_url = format!(
+            "{}/rest/api/1.0/projects/{}/repos/{}",
+            self.url, repo.project.key, repo.slug
+        );
+        // `default-branch` replaces `branches/default` on newer versions, but older versions
+        // only know about the latter.
+        for endpoint in ["default-branch", "branches/default"] {
+            let response = client.call(
+                client
+                    .get(&format!("{}/{}", repo_url, endpoint))
+                    .set("Authorization", auth_header),
+            );
+            match response {
+                Ok(response) if response.status() == 200 => {
+                    let branch: BitbucketServerBranch = response.into_json()?;
+                    return Ok(Some(branch.display_id));
+                }
+                Ok(_) => return Ok(None),
+                Err(ureq::Error::Status(404, _)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Provider for BitbucketServerProvider {
+    fn correctly_configured(&self) -> bool {
+        if let Err(e) = self.token.resolve(&self.env_var, &self.url) {
+            println!("{}", style(format!("Error: {:#}", e)).red());
+            println!("Create an HTTP access token here:");
+            println!("{}/plugins/servlet/access-tokens/manage", self.url);
+            println!(
+                "Then set a {} environment variable with the value",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.name.contains('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that project keys do not contain forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    fn archived(&self) -> Archived {
+        self.archived
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.token)
+    }
+
+    fn scope(&self) -> String {
+        format!("{}/{}", url_scope(&self.url), self.name)
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        let bitbucket_token = self.token.resolve(&self.env_var, &self.url)?;
+        let auth_header = format!("Bearer {}", bitbucket_token);
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = HttpClient::new(&self.http, &self.url)?;
+
+        let mut start = 0;
+        let mut repos = Vec::new();
+
+        loop {
+            let url = format!(
+                "{}/rest/api/1.0/projects/{}/repos?start={}&limit=100",
+                self.url, self.name, start
+            );
+
+            let page: BitbucketServerPage = client
+                .call(client.get(&url).set("Authorization", &auth_header))?
+                .into_json()?;
+
+            for repo in page.values {
+                let full_name = format!("{}/{}", repo.project.key.to_lowercase(), repo.slug);
+                let Some(path) = self.archived.base_path(&self.path, repo.archived) else {
+                    continue;
+                };
+                if (self.skip_forks && repo.origin.is_some())
+                    || !include_regex_set.is_match(&full_name)
+                    || exclude_regex_set.is_match(&full_name)
+                {
+                    continue;
+                }
+                repos.push((path, full_name, repo));
+            }
+
+            match page.next_page_start {
+                Some(next_page_start) if !page.is_last_page => start = next_page_start,
+                _ => break,
+            }
+        }
+
+        // The default branch needs one request per repository, so fetch them in parallel.
+        repos
+            .par_iter()
+            .map(|(path, full_name, repo)| {
+                let url = repo
+                    .links
+                    .clone_url(self.auth_http)
+                    .with_context(|| format!("Repository {} has no clone URL", full_name))?;
+                let upstream = repo
+                    .origin
+                    .as_ref()
+                    .and_then(|origin| origin.links.clone_url(self.auth_http));
+                let branch = self
+                    .fetch_default_branch(&client, &auth_header, repo)
+                    .with_context(|| format!("Error fetching default branch of {}", full_name))?;
+                Ok(
+                    Repository::from_full_name(path, full_name, url, branch, upstream)
+                        .with_id(repo.id),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Response, TestServer};
+    use serde_json::json;
+
+    fn repository(id: u64, slug: &str, archived: bool, fork: bool) -> serde_json::Value {
+        let links = |slug: &str| {
+            json!({"clone": [
+                {"name": "http", "href": format!("https://bitbucket.company.com/scm/proj/{}.git", slug)},
+                {"name": "ssh", "href": format!("ssh://git@bitbucket.company.com:7999/proj/{}.git", slug)}
+            ]})
+        };
+        let mut repository = json!({
+            "id": id,
+            "slug": slug,
+            "project": {"key": "PROJ"},
+            "archived": archived,
+            "links": links(slug),
+        });
+        if fork {
+            repository["origin"] = json!({"slug": "upstream", "links": links("upstream")});
+        }
+        repository
+    }
+
+    fn server() -> TestServer {
+        TestServer::start(|request| {
+            let body = match request.target.as_str() {
+                "/rest/api/1.0/projects/PROJ/repos?start=0&limit=100" => json!({
+                    "values": [repository(1, "api", false, false), repository(2, "old", true, false)],
+                    "isLastPage": false,
+                    "nextPageStart": 2,
+                }),
+                "/rest/api/1.0/projects/PROJ/repos?start=2&limit=100" => json!({
+                    "values": [repository(3, "fork", false, true), repository(4, "empty", false, false)],
+                    "isLastPage": true,
+                }),
+                "/rest/api/1.0/projects/PROJ/repos/api/default-branch" => {
+                    json!({"id": "refs/heads/main", "displayId": "main"})
+                }
+                // Older versions only know about `branches/default`
+                "/rest/api/1.0/projects/PROJ/repos/old/branches/default"
+                | "/rest/api/1.0/projects/PROJ/repos/fork/branches/default" => {
+                    json!({"id": "refs/heads/develop", "displayId": "develop"})
+                }
+                // Empty repositories have no default branch
+                "/rest/api/1.0/projects/PROJ/repos/empty/default-branch" => {
+                    return Some(Response::status(204))
+                }
+                _ => return None,
+            };
+            Some(Response::json(body))
+        })
+    }
+
+    fn provider(server: &TestServer) -> BitbucketServerProvider {
+        BitbucketServerProvider {
+            name: "PROJ".to_string(),
+            url: server.url.clone(),
+            path: "bitbucket-server".to_string(),
+            token: TokenSource {
+                token_command: Some("echo secret".to_string()),
+                ..Default::default()
+            },
+            http: HttpOptions {
+                allow_insecure_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fetch_repositories() {
+        let server = server();
+        let mut provider = provider(&server);
+        provider.archived = Archived::Separate;
+        let mut repositories = provider.fetch_repositories().unwrap();
+        repositories.sort();
+
+        let summary: Vec<_> = repositories
+            .iter()
+            .map(|r| (r.name().as_str(), r.branch.as_deref(), r.id.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "bitbucket-server-archived/proj/old",
+                    Some("develop"),
+                    Some("2")
+                ),
+                ("bitbucket-server/proj/api", Some("main"), Some("1")),
+                ("bitbucket-server/proj/empty", None, Some("4")),
+                ("bitbucket-server/proj/fork", Some("develop"), Some("3")),
+            ]
+        );
+        assert_eq!(
+            repositories[1].url(),
+            "ssh://git@bitbucket.company.com:7999/proj/api.git"
+        );
+        assert_eq!(
+            repositories[3].upstream.as_deref(),
+            Some("ssh://git@bitbucket.company.com:7999/proj/upstream.git")
+        );
+        assert!(server
+            .requests()
+            .iter()
+            .all(|request| request.header("Authorization") == Some("Bearer secret")));
+    }
+
+    #[test]
+    fn test_fetch_repositories_filters() {
+        let server = server();
+        let mut provider = provider(&server);
+        provider.skip_forks = true;
+        provider.auth_http = true;
+        provider.exclude = vec!["empty".to_string()];
+        let repositories = provider.fetch_repositories().unwrap();
+
+        let names: Vec<_> = repositories.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["bitbucket-server/proj/api"]);
+        assert_eq!(
+            repositories[0].url(),
+            "https://bitbucket.company.com/scm/proj/api.git"
+        );
+    }
+}
@@ -0,0 +1,302 @@
+// Errors are returned as they are by ureq, like the rest of the HTTP client.
+#![allow(clippy::result_large_err)]
+
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// The body is stored decoded, so these headers don't describe it anymore
+static SKIPPED_HEADERS: &[&str] = &[
+    "connection",
+    "content-encoding",
+    "content-length",
+    "transfer-encoding",
+];
+
+/// A response stored with the validators used to revalidate it
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+struct CacheEntry {
+    key: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// When the response was received or last revalidated, in seconds since the UNIX epoch
+    #[serde(default)]
+    stored_at: u64,
+    status: u16,
+    status_text: String,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl CacheEntry {
+    fn has_validators(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+
+    fn is_fresh(&self, ttl: Duration, now: u64) -> bool {
+        now.saturating_sub(self.stored_at) < ttl.as_secs()
+    }
+
+    fn to_response(&self) -> Result<ureq::Response, ureq::Error> {
+        let mut response = format!("HTTP/1.1 {} {}\r\n", self.status, self.status_text);
+        for (name, value) in &self.headers {
+            response.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        response.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+        response.push_str(&self.body);
+        response.parse()
+    }
+}
+
+fn hash(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Stores API responses on disk, and sends `If-None-Match` and `If-Modified-Since` headers so
+/// that unchanged responses are not sent again. Responses without these validators, like
+/// GraphQL pages, are only stored if they are reused for some time without being sent again.
+/// The cache is best effort: errors reading or writing it are ignored.
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    /// Responses without validators are reused for `ttl`, or not stored if it's zero
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// Remove the entries of `dir` that were not used since `since`. Entries are written again
+    /// each time they are used, so this removes the responses of requests that are not sent
+    /// anymore, e.g. of removed providers or of pages that don't exist anymore.
+    pub fn prune(dir: &Path, since: SystemTime) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        // Some file systems store modification times with a precision of a second or two
+        let since = since.checked_sub(Duration::from_secs(2)).unwrap_or(since);
+        for entry in entries.flatten() {
+            let modified = entry.metadata().and_then(|metadata| metadata.modified());
+            if modified.is_ok_and(|modified| modified < since) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+
+    /// Requests are identified by their URL, including the query, and their body. Different
+    /// tokens may see different repositories, so a hash of the authorization is included too.
+    fn key(request: &ureq::Request, body: Option<&str>) -> String {
+        format!(
+            "{} {}\n{:016x}\n{}",
+            request.method(),
+            request.url(),
+            hash(request.header("Authorization")),
+            body.unwrap_or_default()
+        )
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.json", hash(key)))
+    }
+
+    fn read(&self, key: &str) -> Option<CacheEntry> {
+        let contents = fs::read_to_string(self.path(key)).ok()?;
+        let entry: CacheEntry = serde_json::from_str(&contents).ok()?;
+        // Guard against hash collisions
+        Some(entry).filter(|entry| entry.key == key)
+    }
+
+    fn write(&self, entry: &CacheEntry) {
+        let path = self.path(&entry.key);
+        // Providers are fetched in parallel, so write to a temporary file and rename it
+        let temp_path = path.with_extension(format!("{:?}.tmp", thread::current().id()));
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&temp_path, serde_json::to_vec(entry)?))
+            .and_then(|_| fs::rename(&temp_path, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&temp_path);
+        }
+    }
+
+    fn store(&self, key: String, response: ureq::Response) -> Result<ureq::Response, ureq::Error> {
+        let etag = response.header("ETag").map(str::to_string);
+        let last_modified = response.header("Last-Modified").map(str::to_string);
+        if etag.is_none() && last_modified.is_none() && self.ttl.is_zero() {
+            return Ok(response);
+        }
+
+        let headers = response
+            .headers_names()
+            .into_iter()
+            .filter(|name| !SKIPPED_HEADERS.contains(&name.as_str()))
+            .flat_map(|name| {
+                response
+                    .all(&name)
+                    .into_iter()
+                    .map(|value| (name.clone(), value.to_string()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let entry = CacheEntry {
+            key,
+            etag,
+            last_modified,
+            stored_at: now(),
+            status: response.status(),
+            status_text: response.status_text().to_string(),
+            headers,
+            body: response.into_string()?,
+        };
+        self.write(&entry);
+        entry.to_response()
+    }
+
+    /// Send `request` with `send`, revalidating the cached response if there is one. `body` is
+    /// the body sent with the request, if any.
+    pub fn send(
+        &self,
+        request: ureq::Request,
+        body: Option<&str>,
+        send: impl FnOnce(ureq::Request) -> Result<ureq::Response, ureq::Error>,
+    ) -> Result<ureq::Response, ureq::Error> {
+        let key = Self::key(&request, body);
+        let cached = self.read(&key);
+
+        let mut request = request;
+        if let Some(entry) = &cached {
+            if !entry.has_validators() && entry.is_fresh(self.ttl, now()) {
+                // Written again to mark it as used
+                self.write(entry);
+                return entry.to_response();
+            }
+            if let Some(etag) = &entry.etag {
+                request = request.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request = request.set("If-Modified-Since", last_modified);
+            }
+        }
+
+        let response = send(request)?;
+        match cached {
+            Some(mut entry) if response.status() == 304 => {
+                entry.stored_at = now();
+                self.write(&entry);
+                entry.to_response()
+            }
+            _ => self.store(key, response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_send() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache = ResponseCache::new(temp_dir.path().join("cache"), Duration::ZERO);
+        let request = || ureq::get("https://gitea.com/api/v1/orgs/acme/repos?page=1");
+
+        let response = cache
+            .send(request(), None, |request| {
+                assert_eq!(request.header("If-None-Match"), None);
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nX-Total-Count: 2\r\n\r\n[1, 2]".parse()
+            })
+            .unwrap();
+        assert_eq!(response.header("X-Total-Count"), Some("2"));
+        assert_eq!(response.into_string().unwrap(), "[1, 2]");
+
+        let response = cache
+            .send(request(), None, |request| {
+                assert_eq!(request.header("If-None-Match"), Some("\"v1\""));
+                "HTTP/1.1 304 Not Modified\r\n\r\n".parse()
+            })
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.header("X-Total-Count"), Some("2"));
+        assert_eq!(response.into_string().unwrap(), "[1, 2]");
+
+        // Other tokens or bodies don't share the cached response
+        let other_token = request().set("Authorization", "token other");
+        cache
+            .send(other_token, None, |request| {
+                assert_eq!(request.header("If-None-Match"), None);
+                "HTTP/1.1 200 OK\r\n\r\n[]".parse()
+            })
+            .unwrap();
+        cache
+            .send(request(), Some("{}"), |request| {
+                assert_eq!(request.header("If-None-Match"), None);
+                "HTTP/1.1 200 OK\r\n\r\n[]".parse()
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn test_send_without_validators() {
+        let temp_dir = TempDir::new().unwrap();
+        let request = || ureq::post("https://gitlab.com/api/graphql");
+        let query = Some(r#"{"query": "..."}"#);
+
+        // Without a TTL, responses that can't be revalidated are always sent again
+        let cache = ResponseCache::new(temp_dir.path().join("cache"), Duration::ZERO);
+        for _ in 0..2 {
+            cache
+                .send(request(), query, |_| "HTTP/1.1 200 OK\r\n\r\n{}".parse())
+                .unwrap();
+        }
+        assert!(!temp_dir.path().join("cache").exists());
+
+        let cache = ResponseCache::new(temp_dir.path().join("cache"), Duration::from_secs(60));
+        cache
+            .send(request(), query, |_| {
+                "HTTP/1.1 200 OK\r\n\r\n{\"page\": 1}".parse()
+            })
+            .unwrap();
+        let response = cache
+            .send(request(), query, |_| panic!("The cached response is fresh"))
+            .unwrap();
+        assert_eq!(response.into_string().unwrap(), "{\"page\": 1}");
+
+        let entry = cache.read(&ResponseCache::key(&request(), query)).unwrap();
+        assert!(entry.is_fresh(Duration::from_secs(60), entry.stored_at + 59));
+        assert!(!entry.is_fresh(Duration::from_secs(60), entry.stored_at + 60));
+    }
+
+    #[test]
+    fn test_prune() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path().join("cache");
+        let cache = ResponseCache::new(dir.clone(), Duration::from_secs(60));
+        cache
+            .send(ureq::get("https://gitea.com/api/v1/version"), None, |_| {
+                "HTTP/1.1 200 OK\r\n\r\n{}".parse()
+            })
+            .unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Entries used since then are kept, the others are removed
+        let now = SystemTime::now();
+        ResponseCache::prune(&dir, now - Duration::from_secs(60));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        ResponseCache::prune(&dir, now + Duration::from_secs(60));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::providers::{check_repository_path, ManualRepository, Provider};
+use crate::repository::Repository;
+use anyhow::{anyhow, Context};
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add repositories listed by an external command")]
+pub struct CommandProvider {
+    /// The command to run. It must print a JSON array of repositories to stdout, each with a
+    /// `path` and `url`, and optionally a `branch` and `upstream`.
+    pub command: String,
+
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    #[serde(default)]
+    /// Arguments given to the command
+    args: Vec<String>,
+
+    #[arg(long = "path")]
+    #[serde(default)]
+    /// Prefix the paths of the repositories with a specific base path
+    path: Option<String>,
+
+    #[arg(skip)]
+    #[serde(skip)]
+    /// The workspace directory, which the command runs in
+    workspace: PathBuf,
+}
+
+impl fmt::Display for CommandProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Repositories listed by {}",
+            style(format!("{} {}", self.command, self.args.join(" ")).trim_end()).green(),
+        )?;
+        if let Some(path) = &self.path {
+            write!(f, " in directory {}", style(path).green())?;
+        }
+        Ok(())
+    }
+}
+
+impl Provider for CommandProvider {
+    fn correctly_configured(&self) -> bool {
+        if self.command.is_empty() {
+            println!("{}", style("Error: The command cannot be empty").red());
+            return false;
+        }
+        true
+    }
+
+    fn scope(&self) -> String {
+        let mut scope = self.command.clone();
+        for arg in &self.args {
+            // Quote arguments that would be read as several ones
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                scope.push_str(&format!(" {:?}", arg));
+            } else {
+                scope.push_str(&format!(" {}", arg));
+            }
+        }
+        scope
+    }
+
+    fn set_workspace(&mut self, workspace: &Path) {
+        self.workspace = workspace.to_path_buf();
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        let mut command = Command::new(&self.command);
+        if !self.workspace.as_os_str().is_empty() {
+            command.current_dir(&self.workspace);
+        }
+        let output = command
+            .args(&self.args)
+            .stdin(Stdio::null())
+            .output()
+            .with_context(|| format!("Error running command {}", self.command))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(anyhow!(
+                "Command {} exited with {}: {}",
+                self.command,
+                output.status,
+                stderr.trim()
+            ));
+        }
+
+        let repositories: Vec<ManualRepository> = serde_json::from_slice(&output.stdout)
+            .with_context(|| format!("Error parsing the output of {}", self.command))?;
+
+        repositories
+            .into_iter()
+            .map(|mut repo| {
+                // The command could otherwise clone repositories anywhere
+                check_repository_path(&repo.path).with_context(|| {
+                    format!("Invalid path for {} from {}", repo.url, self.command)
+                })?;
+                if let Some(path) = &self.path {
+                    repo.path = format!("{}/{}", path, repo.path);
+                    check_repository_path(&repo.path)?;
+                }
+                Ok(Repository::from(repo))
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn provider(script: &str, path: Option<&str>) -> CommandProvider {
+        CommandProvider {
+            command: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            path: path.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fetch_repositories() {
+        let provider = provider(
+            r#"echo '[{"path": "tools/a", "url": "https://example.com/a.git", "branch": "main"}, {"path": "b", "url": "git@example.com:b.git"}]'"#,
+            Some("catalog"),
+        );
+        let repositories = provider.fetch_repositories().unwrap();
+        assert_eq!(repositories.len(), 2);
+        assert_eq!(repositories[0].name(), "catalog/tools/a");
+        assert_eq!(repositories[0].branch, Some("main".to_string()));
+        assert_eq!(repositories[1].name(), "catalog/b");
+        assert_eq!(repositories[1].branch, None);
+    }
+
+    #[test]
+    fn test_fetch_repositories_errors() {
+        let failing = provider("echo oops >&2; exit 3", None);
+        let error = failing.fetch_repositories().unwrap_err();
+        assert!(error.to_string().contains("oops"));
+
+        let invalid = provider("echo not json", None);
+        assert!(invalid.fetch_repositories().is_err());
+
+        for path in ["/srv/a", "../a", "tools/../../a"] {
+            let outside = provider(
+                &format!(r#"echo '[{{"path": "{}", "url": "a.git"}}]'"#, path),
+                None,
+            );
+            assert!(outside.fetch_repositories().is_err(), "{}", path);
+        }
+        let outside = provider(r#"echo '[{"path": "a", "url": "a.git"}]'"#, Some(".."));
+        assert!(outside.fetch_repositories().is_err());
+    }
+
+    #[test]
+    fn test_runs_in_workspace() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let workspace = temp_dir.path().canonicalize().unwrap();
+        let mut provider = provider(
+            r#"printf '[{"path": "a", "url": "%s/a.git"}]' "$(pwd)""#,
+            None,
+        );
+        provider.set_workspace(&workspace);
+        let repositories = provider.fetch_repositories().unwrap();
+        assert_eq!(
+            repositories[0].url(),
+            format!("{}/a.git", workspace.display())
+        );
+    }
+}
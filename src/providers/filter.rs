@@ -0,0 +1,282 @@
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(
+    Deserialize, Serialize, Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    Public,
+    Private,
+    Internal,
+}
+
+/// How archived repositories are handled
+#[derive(
+    Deserialize,
+    Serialize,
+    Default,
+    Clone,
+    Copy,
+    Debug,
+    Eq,
+    Ord,
+    PartialEq,
+    PartialOrd,
+    clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Archived {
+    /// Don't clone archived repositories
+    #[default]
+    Skip,
+    /// Clone archived repositories alongside the others
+    Include,
+    /// Clone archived repositories to a separate `<path>-archived` directory
+    Separate,
+}
+
+impl Archived {
+    /// The base path to clone a repository to, or None if it should be skipped
+    pub fn base_path(&self, path: &str, archived: bool) -> Option<String> {
+        match (self, archived) {
+            (_, false) | (Archived::Include, true) => Some(path.to_string()),
+            (Archived::Skip, true) => None,
+            (Archived::Separate, true) => Some(format!("{}-archived", path)),
+        }
+    }
+}
+
+/// A period of time written as a number followed by a unit, like `12h`, `30d`, `8w` or `1y`
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[serde(try_from = "String", into = "String")]
+pub struct Period {
+    amount: u32,
+    unit: char,
+}
+
+impl Period {
+    /// The duration of the period, or None if it's too long to be represented
+    fn as_time_delta(&self) -> Option<TimeDelta> {
+        let amount = i64::from(self.amount);
+        match self.unit {
+            'h' => TimeDelta::try_hours(amount),
+            'd' => TimeDelta::try_days(amount),
+            'w' => TimeDelta::try_weeks(amount),
+            _ => TimeDelta::try_days(amount * 365),
+        }
+    }
+}
+
+impl FromStr for Period {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let s = s.trim();
+        let unit = s
+            .chars()
+            .last()
+            .filter(|unit| matches!(unit, 'h' | 'd' | 'w' | 'y'))
+            .with_context(|| {
+                format!(
+                    "Invalid period {:?}, expected a number followed by h, d, w or y",
+                    s
+                )
+            })?;
+        let amount = s[..s.len() - 1]
+            .parse()
+            .map_err(|_| anyhow!("Invalid period {:?}, expected a number before the unit", s))?;
+        let period = Self { amount, unit };
+        if period.as_time_delta().is_none() {
+            anyhow::bail!("Invalid period {:?}, it is too long", s);
+        }
+        Ok(period)
+    }
+}
+
+impl TryFrom<String> for Period {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<Period> for String {
+    fn from(period: Period) -> Self {
+        period.to_string()
+    }
+}
+
+impl fmt::Display for Period {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.amount, self.unit)
+    }
+}
+
+/// Filters on the metadata of repositories, for providers that expose it. Empty filters match
+/// every repository.
+#[derive(
+    Deserialize, Serialize, Default, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args,
+)]
+pub struct MetadataFilter {
+    #[arg(long = "topic")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Only clone repositories that have at least one of these topics
+    pub topics: Vec<String>,
+
+    #[arg(long = "language")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Only clone repositories whose primary language is one of these, ignoring case
+    pub languages: Vec<String>,
+
+    #[arg(long = "visibility", value_enum)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    /// Only clone repositories with one of these visibilities
+    pub visibility: Vec<Visibility>,
+
+    #[arg(long = "pushed-within")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Only clone repositories that have been pushed to within this period, like `365d`.
+    /// Supported units are h, d, w and y.
+    pub pushed_within: Option<Period>,
+}
+
+impl MetadataFilter {
+    pub fn is_match(
+        &self,
+        topics: &[String],
+        language: Option<&str>,
+        visibility: Visibility,
+        pushed_at: Option<&str>,
+    ) -> bool {
+        let topic_matches = self.topics.is_empty()
+            || topics
+                .iter()
+                .any(|topic| self.topics.iter().any(|t| t.eq_ignore_ascii_case(topic)));
+        let language_matches = self.languages.is_empty()
+            || language.is_some_and(|language| {
+                self.languages
+                    .iter()
+                    .any(|l| l.eq_ignore_ascii_case(language))
+            });
+        let visibility_matches =
+            self.visibility.is_empty() || self.visibility.contains(&visibility);
+        let pushed_matches = match self.pushed_within {
+            None => true,
+            // Repositories that were never pushed to are stale. Timestamps that can't be parsed
+            // are kept, so that an unexpected format doesn't empty the workspace. Periods going
+            // back further than the earliest date keep every repository.
+            Some(period) => pushed_at.is_some_and(|pushed_at| {
+                let since = period
+                    .as_time_delta()
+                    .and_then(|delta| Utc::now().checked_sub_signed(delta));
+                DateTime::parse_from_rfc3339(pushed_at).map_or(true, |pushed_at| {
+                    since.map_or(true, |since| pushed_at > since)
+                })
+            }),
+        };
+        topic_matches && language_matches && visibility_matches && pushed_matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_filter_matches_everything() {
+        let filter = MetadataFilter::default();
+        assert!(filter.is_match(&[], None, Visibility::Private, None));
+        assert!(filter.is_match(
+            &["backend".to_string()],
+            Some("Rust"),
+            Visibility::Public,
+            Some("2015-01-01T00:00:00Z")
+        ));
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = MetadataFilter {
+            topics: vec!["backend".to_string(), "api".to_string()],
+            languages: vec!["rust".to_string()],
+            visibility: vec![Visibility::Private, Visibility::Internal],
+            pushed_within: None,
+        };
+        let topics = ["Backend".to_string(), "payments".to_string()];
+        assert!(filter.is_match(&topics, Some("Rust"), Visibility::Internal, None));
+        assert!(!filter.is_match(&topics, Some("Rust"), Visibility::Public, None));
+        assert!(!filter.is_match(&topics, Some("Go"), Visibility::Private, None));
+        assert!(!filter.is_match(&topics, None, Visibility::Private, None));
+        assert!(!filter.is_match(&[], Some("Rust"), Visibility::Private, None));
+    }
+
+    #[test]
+    fn test_archived_base_path() {
+        assert_eq!(
+            Archived::Skip.base_path("github", false),
+            Some("github".to_string())
+        );
+        assert_eq!(Archived::Skip.base_path("github", true), None);
+        assert_eq!(
+            Archived::Include.base_path("github", true),
+            Some("github".to_string())
+        );
+        assert_eq!(
+            Archived::Separate.base_path("github", false),
+            Some("github".to_string())
+        );
+        assert_eq!(
+            Archived::Separate.base_path("github", true),
+            Some("github-archived".to_string())
+        );
+    }
+
+    #[test]
+    fn test_period() {
+        assert_eq!("365d".parse::<Period>().unwrap().to_string(), "365d");
+        assert_eq!(
+            "2w".parse::<Period>().unwrap().as_time_delta(),
+            Some(TimeDelta::days(14))
+        );
+        assert!("".parse::<Period>().is_err());
+        assert!("4000000000y".parse::<Period>().is_err());
+        assert!("d".parse::<Period>().is_err());
+        assert!("12".parse::<Period>().is_err());
+        assert!("3 months".parse::<Period>().is_err());
+    }
+
+    #[test]
+    fn test_pushed_within() {
+        let filter = MetadataFilter {
+            pushed_within: Some("30d".parse().unwrap()),
+            ..Default::default()
+        };
+        let recent = (Utc::now() - TimeDelta::days(2)).to_rfc3339();
+        assert!(filter.is_match(&[], None, Visibility::Public, Some(&recent)));
+        assert!(filter.is_match(
+            &[],
+            None,
+            Visibility::Public,
+            Some(
+                &(Utc::now() - TimeDelta::days(2))
+                    .format("%Y-%m-%dT%H:%M:%S+08:00")
+                    .to_string()
+            )
+        ));
+        assert!(!filter.is_match(&[], None, Visibility::Public, Some("2015-01-01T00:00:00Z")));
+        assert!(!filter.is_match(&[], None, Visibility::Public, None));
+
+        // Longer than the supported dates, but not than durations
+        let filter = MetadataFilter {
+            pushed_within: Some("1000000000d".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.is_match(&[], None, Visibility::Public, Some("1970-01-01T00:00:00Z")));
+        assert!(!filter.is_match(&[], None, Visibility::Public, None));
+    }
+}
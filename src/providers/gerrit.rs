@@ -0,0 +1,431 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, url_scope, Archived, HttpClient,
+    HttpOptions, PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::Context;
+use base64::{engine::general_purpose, Engine};
+use console::style;
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+
+// Gerrit prefixes all JSON responses with this to prevent cross-site script inclusion.
+// See https://gerrit-review.googlesource.com/Documentation/rest-api.html#output
+static XSSI_PREFIX: &str = ")]}'";
+
+// Number of projects fetched per page
+static PAGE_SIZE: usize = 500;
+
+#[derive(Deserialize, Debug)]
+struct GerritProject {
+    #[serde(default)]
+    state: Option<String>,
+    #[serde(rename = "_more_projects", default)]
+    more_projects: bool,
+}
+
+fn default_env_var() -> String {
+    String::from("GERRIT_HTTP_CREDENTIALS")
+}
+
+/// Parse a JSON response from Gerrit, stripping the XSSI prefix
+fn parse_response<T: DeserializeOwned>(body: &str) -> anyhow::Result<T> {
+    let json = body.trim_start().trim_start_matches(XSSI_PREFIX);
+    serde_json::from_str(json).context("Error parsing Gerrit response")
+}
+
+/// Gerrit project names can contain slashes, which need to be encoded in URLs
+fn encode_project_name(name: &str) -> String {
+    name.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add all projects of a Gerrit instance")]
+pub struct GerritProvider {
+    /// Gerrit instance URL
+    pub url: String,
+
+    #[arg(long = "path", default_value = "gerrit")]
+    /// Clone repositories to a specific base path
+    path: String,
+
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
+
+    #[arg(
+        long = "env-name",
+        short = 'e',
+        default_value = "GERRIT_HTTP_CREDENTIALS"
+    )]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the HTTP credentials, as `username:password`. Projects
+    /// are listed anonymously if it is not defined.
+    env_var: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    http: HttpOptions,
+
+    #[arg(long = "ssh-url")]
+    #[serde(default)]
+    /// Base SSH URL used to clone projects, defaults to ssh://HOSTNAME:29418
+    ssh_url: Option<String>,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone projects that match these regular expressions
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone projects that match these regular expressions
+    exclude: Vec<String>,
+
+    #[arg(long = "archived", value_enum, default_value_t)]
+    #[serde(default)]
+    /// Skip read only projects, include them, or clone them to a separate `<path>-archived`
+    /// directory
+    archived: Archived,
+}
+
+impl fmt::Display for GerritProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Gerrit projects at {} in directory {}, using the credentials from {}",
+            style(&self.url).green(),
+            style(&self.path).green(),
+            style(self.token.describe(&self.env_var)).green(),
+        )
+    }
+}
+
+impl GerritProvider {
+    /// The HTTP credentials, if any. Projects are listed anonymously without them.
+    fn credentials(&self) -> anyhow::Result<Option<String>> {
+        if !self.token.is_configured() && env::var(&self.env_var).is_err() {
+            return Ok(None);
+        }
+        self.token
+            .resolve_username_password(&self.env_var, &self.url)
+            .map(Some)
+    }
+
+    fn clone_url(&self, project: &str) -> String {
+        if self.auth_http {
+            // The /a/ prefix makes Gerrit ask for credentials instead of hiding private projects
+            return format!("{}/a/{}", self.url, project);
+        }
+        match &self.ssh_url {
+            Some(ssh_url) => format!("{}/{}", ssh_url.trim_end_matches('/'), project),
+            None => {
+                let host = self
+                    .url
+                    .trim_start_matches("https://")
+                    .trim_start_matches("http://")
+                    .split('/')
+                    .next()
+                    .unwrap_or_default();
+                format!("ssh://{}:29418/{}", host, project)
+            }
+        }
+    }
+
+    fn get<T: DeserializeOwned>(
+        &self,
+        client: &HttpClient,
+        auth_header: Option<&str>,
+        path: &str,
+    ) -> anyhow::Result<T> {
+        // Authenticated REST endpoints are prefixed with /a/
+        let url = match auth_header {
+            Some(_) => format!("{}/a/{}", self.url, path),
+            None => format!("{}/{}", self.url, path),
+        };
+        let mut request = client.get(&url);
+        if let Some(auth_header) = auth_header {
+            request = request.set("Authorization", auth_header);
+        }
+        let body = client.call(request)?.into_string()?;
+        parse_response(&body)
+    }
+}
+
+impl Provider for GerritProvider {
+    fn correctly_configured(&self) -> bool {
+        match self.credentials() {
+            Err(e) => {
+                println!("{}", style(format!("Error: {:#}", e)).red());
+                return false;
+            }
+            Ok(Some(credentials)) if !credentials.contains(':') => {
+                println!(
+                    "{}",
+                    style(format!(
+                        "Error: {} must be formatted as username:password",
+                        self.env_var
+                    ))
+                    .red()
+                );
+                println!("Generate an HTTP password here:");
+                println!("{}/settings/#HTTPCredentials", self.url);
+                return false;
+            }
+            Ok(_) => {}
+        }
+        if self.url.ends_with('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that URLs do not end in forward slashes").red()
+            );
+            println!("You specified: {}", self.url);
+            return false;
+        }
+        true
+    }
+
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    fn archived(&self) -> Archived {
+        self.archived
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.token)
+    }
+
+    fn scope(&self) -> String {
+        // Gerrit instances are added as a whole
+        url_scope(&self.url).to_string()
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        let auth_header = self.credentials()?.map(|credentials| {
+            format!(
+                "Basic {}",
+                general_purpose::STANDARD.encode(credentials.as_bytes())
+            )
+        });
+        let auth_header = auth_header.as_deref();
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = HttpClient::new(&self.http, &self.url)?;
+
+        let mut projects = Vec::new();
+        let mut skip = 0;
+
+        loop {
+            let page: BTreeMap<String, GerritProject> = self.get(
+                &client,
+                auth_header,
+                &format!("projects/?d&type=CODE&n={}&S={}", PAGE_SIZE, skip),
+            )?;
+            let more_projects = page.values().any(|p| p.more_projects);
+            skip += page.len();
+
+            projects.extend(
+                page.into_iter()
+                    .filter(|(name, _)| include_regex_set.is_match(name))
+                    .filter(|(name, _)| !exclude_regex_set.is_match(name))
+                    .filter_map(|(name, p)| {
+                        // Read only projects are the Gerrit equivalent of archived ones, while
+                        // hidden projects can't be cloned at all.
+                        let archived = match p.state.as_deref().unwrap_or("ACTIVE") {
+                            "ACTIVE" => false,
+                            "READ_ONLY" => true,
+                            _ => return None,
+                        };
+                        let path = self.archived.base_path(&self.path, archived)?;
+                        Some((name, path))
+                    }),
+            );
+
+            if !more_projects {
+                break;
+            }
+        }
+
+        // Resolving HEAD needs one request per project, and instances often host hundreds.
+        projects
+            .par_iter()
+            .map(|(name, path)| {
+                let head: String = self
+                    .get(
+                        &client,
+                        auth_header,
+                        &format!("projects/{}/HEAD", encode_project_name(name)),
+                    )
+                    .with_context(|| format!("Error fetching HEAD of {}", name))?;
+                let branch = head.strip_prefix("refs/heads/").map(String::from);
+                Ok(
+                    Repository::from_full_name(path, name, self.clone_url(name), branch, None)
+                        // Gerrit projects cannot be renamed, their name is their identifier
+                        .with_id(name),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Response, TestServer};
+    use serde_json::json;
+
+    /// A JSON response with the XSSI prefix of Gerrit
+    fn gerrit_json(body: serde_json::Value) -> Response {
+        Response::json(format!("{}\n{}", XSSI_PREFIX, body))
+    }
+
+    fn server() -> TestServer {
+        TestServer::start(|request| {
+            // Authenticated requests are prefixed with /a/
+            let target = request.target.strip_prefix("/a").unwrap_or(&request.target);
+            match target {
+                "/projects/?d&type=CODE&n=500&S=0" => Some(gerrit_json(json!({
+                    "platform/build": {"id": "platform%2Fbuild", "state": "ACTIVE"},
+                    "tools": {"id": "tools", "state": "READ_ONLY", "_more_projects": true},
+                }))),
+                "/projects/?d&type=CODE&n=500&S=2" => Some(gerrit_json(json!({
+                    "secret": {"id": "secret", "state": "HIDDEN"},
+                    "web": {"id": "web"},
+                }))),
+                "/projects/platform%2Fbuild/HEAD" => Some(gerrit_json(json!("refs/heads/main"))),
+                "/projects/tools/HEAD" => Some(gerrit_json(json!("refs/heads/master"))),
+                "/projects/web/HEAD" => Some(gerrit_json(json!("refs/heads/develop"))),
+                _ => None,
+            }
+        })
+    }
+
+    fn provider(server: &TestServer) -> GerritProvider {
+        GerritProvider {
+            url: server.url.clone(),
+            path: "gerrit".to_string(),
+            env_var: "GIT_WORKSPACE_TEST_UNDEFINED_TOKEN".to_string(),
+            http: HttpOptions {
+                allow_insecure_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fetch_repositories() {
+        let server = server();
+        let mut provider = provider(&server);
+        provider.token.token_command = Some("echo me:secret".to_string());
+        provider.archived = Archived::Separate;
+        let mut repositories = provider.fetch_repositories().unwrap();
+        repositories.sort();
+
+        // Hidden projects are skipped, read only ones are archived
+        let summary: Vec<_> = repositories
+            .iter()
+            .map(|r| (r.name().as_str(), r.branch.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("gerrit-archived/tools", Some("master")),
+                ("gerrit/platform/build", Some("main")),
+                ("gerrit/web", Some("develop")),
+            ]
+        );
+
+        let requests = server.requests();
+        // Two pages of projects, and the HEAD of each project
+        assert_eq!(requests.len(), 5);
+        let auth_header = format!("Basic {}", general_purpose::STANDARD.encode("me:secret"));
+        assert!(requests.iter().all(|request| {
+            request.target.starts_with("/a/projects/")
+                && request.header("Authorization") == Some(auth_header.as_str())
+        }));
+    }
+
+    #[test]
+    fn test_fetch_repositories_anonymously() {
+        let server = server();
+        let repositories = provider(&server).fetch_repositories().unwrap();
+        assert_eq!(repositories.len(), 2);
+
+        assert!(server.requests().iter().all(|request| {
+            request.target.starts_with("/projects/") && request.header("Authorization").is_none()
+        }));
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = ")]}'\n{\"platform/build\":{\"id\":\"platform%2Fbuild\",\"state\":\"ACTIVE\"},\"tools\":{\"id\":\"tools\",\"state\":\"READ_ONLY\",\"_more_projects\":true}}";
+        let projects: BTreeMap<String, GerritProject> = parse_response(body).unwrap();
+        assert_eq!(projects.len(), 2);
+        assert_eq!(projects["platform/build"].state.as_deref(), Some("ACTIVE"));
+        assert!(projects["tools"].more_projects);
+
+        let head: String = parse_response(")]}'\n\"refs/heads/main\"").unwrap();
+        assert_eq!(head, "refs/heads/main");
+    }
+
+    #[test]
+    fn test_encode_project_name() {
+        assert_eq!(encode_project_name("platform/build"), "platform%2Fbuild");
+        assert_eq!(encode_project_name("tools"), "tools");
+    }
+
+    #[test]
+    fn test_clone_url() {
+        let mut provider = GerritProvider {
+            url: "https://review.example.com".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            provider.clone_url("platform/build"),
+            "ssh://review.example.com:29418/platform/build"
+        );
+
+        provider.ssh_url = Some("ssh://me@review.example.com:29418/".to_string());
+        assert_eq!(
+            provider.clone_url("platform/build"),
+            "ssh://me@review.example.com:29418/platform/build"
+        );
+
+        provider.auth_http = true;
+        assert_eq!(
+            provider.clone_url("platform/build"),
+            "https://review.example.com/a/platform/build"
+        );
+    }
+}
@@ -1,21 +1,56 @@
 use crate::providers::{
-    create_exclude_regex_set, create_include_regex_set, Provider, APP_USER_AGENT,
+    create_exclude_regex_set, create_include_regex_set, instance_scope, Archived, HttpClient,
+    HttpOptions, MetadataFilter, PathTemplate, Provider, TokenSource, Visibility,
 };
 use crate::repository::Repository;
 use anyhow::Context;
 use console::style;
 use serde::{Deserialize, Serialize};
-use std::env;
 use std::fmt;
 
+#[derive(Deserialize, Debug)]
+struct GiteaParent {
+    clone_url: String,
+    ssh_url: String,
+}
+
 #[derive(Deserialize, Debug)]
 struct GiteaRepository {
+    id: u64,
     full_name: String,
     clone_url: String,
     ssh_url: String,
     default_branch: String,
     archived: bool,
     fork: bool,
+    #[serde(default)]
+    parent: Option<GiteaParent>,
+    #[serde(default)]
+    topics: Vec<String>,
+    #[serde(default)]
+    language: String,
+    private: bool,
+    #[serde(default)]
+    internal: bool,
+    #[serde(default)]
+    updated_at: Option<String>,
+}
+
+impl GiteaRepository {
+    fn visibility(&self) -> Visibility {
+        if self.internal {
+            Visibility::Internal
+        } else if self.private {
+            Visibility::Private
+        } else {
+            Visibility::Public
+        }
+    }
+
+    /// Gitea reports an empty language when it is unknown
+    fn language(&self) -> Option<&str> {
+        Some(self.language.as_str()).filter(|l| !l.is_empty())
+    }
 }
 
 fn default_env_var() -> String {
@@ -28,21 +63,35 @@ fn public_gitea_url() -> String {
     DEFAULT_GITEA_URL.to_string()
 }
 
-#[derive(Deserialize, Serialize, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+fn default_forgejo_env_var() -> String {
+    String::from("FORGEJO_TOKEN")
+}
+
+static DEFAULT_FORGEJO_URL: &str = "https://codeberg.org";
+
+fn public_forgejo_url() -> String {
+    DEFAULT_FORGEJO_URL.to_string()
+}
+
+/// The options shared by [`GiteaProvider`] and [`ForgejoProvider`]
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args)]
 #[serde(rename_all = "lowercase")]
-#[command(about = "Add a Gitea user or organization by name")]
-pub struct GiteaProvider {
+pub struct GiteaOptions {
     /// The name of the user or organisation to add
     pub name: String,
 
-    #[arg(long = "path", default_value = "gitea")]
-    /// Clone repos to a specific path
-    path: String,
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
 
-    #[arg(long = "env-name", short = 'e', default_value = "GITEA_TOKEN")]
-    #[serde(default = "default_env_var")]
-    /// Environment variable containing the auth token
-    env_var: String,
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    http: HttpOptions,
 
     #[arg(long = "skip-forks")]
     #[serde(default)]
@@ -64,80 +113,149 @@ pub struct GiteaProvider {
     /// Don't clone repositories that match these regular expressions
     exclude: Vec<String>,
 
+    #[arg(long = "archived", value_enum, default_value_t)]
+    #[serde(default)]
+    /// Skip archived repositories, include them, or clone them to a separate `<path>-archived`
+    /// directory
+    archived: Archived,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub filter: MetadataFilter,
+}
+
+#[derive(Deserialize, Serialize, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add a Gitea user or organization by name")]
+pub struct GiteaProvider {
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub options: GiteaOptions,
+
+    #[arg(long = "path", default_value = "gitea")]
+    /// Clone repos to a specific path
+    path: String,
+
+    #[arg(long = "env-name", short = 'e', default_value = "GITEA_TOKEN")]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the auth token
+    env_var: String,
+
     #[arg(long = "url", default_value = DEFAULT_GITEA_URL)]
     #[serde(default = "public_gitea_url")]
     /// Gitea instance URL
     pub url: String,
 }
 
-impl fmt::Display for GiteaProvider {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+/// Forgejo is a fork of Gitea sharing the same API. This provider only differs from
+/// [`GiteaProvider`] by its defaults, which point to Codeberg.
+#[derive(Deserialize, Serialize, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add a Forgejo (e.g. Codeberg) user or organization by name")]
+pub struct ForgejoProvider {
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub options: GiteaOptions,
+
+    #[arg(long = "path", default_value = "forgejo")]
+    /// Clone repos to a specific path
+    path: String,
+
+    #[arg(long = "env-name", short = 'e', default_value = "FORGEJO_TOKEN")]
+    #[serde(default = "default_forgejo_env_var")]
+    /// Environment variable containing the auth token
+    env_var: String,
+
+    #[arg(long = "url", default_value = DEFAULT_FORGEJO_URL)]
+    #[serde(default = "public_forgejo_url")]
+    /// Forgejo instance URL
+    pub url: String,
+}
+
+/// A Gitea or Forgejo instance, as configured by one of the providers
+struct GiteaInstance<'a> {
+    options: &'a GiteaOptions,
+    path: &'a str,
+    env_var: &'a str,
+    url: &'a str,
+    default_url: &'static str,
+}
+
+impl GiteaInstance<'_> {
+    fn scope(&self) -> String {
+        instance_scope(self.url, self.default_url, &self.options.name)
+    }
+
+    fn describe(&self, f: &mut fmt::Formatter, kind: &str) -> fmt::Result {
         write!(
             f,
-            "Gitea user/org {} at {} in directory {}, using the token stored in {}",
-            style(&self.name.to_lowercase()).green(),
-            style(&self.url).green(),
-            style(&self.path).green(),
-            style(&self.env_var).green(),
+            "{} user/org {} at {} in directory {}, using the token from {}",
+            kind,
+            style(&self.options.name.to_lowercase()).green(),
+            style(self.url).green(),
+            style(self.path).green(),
+            style(self.options.token.describe(self.env_var)).green(),
         )
     }
-}
 
-impl Provider for GiteaProvider {
+    /// Organizations and users have different endpoints to list their repositories, and
+    /// private organization repositories are only listed by the organization endpoint.
+    fn repositories_url(&self, client: &HttpClient, auth_header: &str) -> anyhow::Result<String> {
+        let name = &self.options.name;
+        let response = client.call(
+            client
+                .get(&format!("{}/api/v1/orgs/{}", self.url, name))
+                .set("Authorization", auth_header),
+        );
+        match response {
+            Ok(_) => Ok(format!("{}/api/v1/orgs/{}/repos", self.url, name)),
+            Err(ureq::Error::Status(404, _)) => {
+                Ok(format!("{}/api/v1/users/{}/repos", self.url, name))
+            }
+            Err(e) => Err(e).with_context(|| format!("Error looking up the organization {}", name)),
+        }
+    }
+
     fn correctly_configured(&self) -> bool {
-        let token = env::var(&self.env_var);
-        if token.is_err() {
-            println!(
-                "{}",
-                style(format!(
-                    "Error: {} environment variable is not defined",
-                    self.env_var
-                ))
-                .red()
-            );
-            println!("Create an access token in your Gitea Settings -> Applications");
+        if let Err(e) = self.options.token.resolve(self.env_var, self.url) {
+            println!("{}", style(format!("Error: {:#}", e)).red());
+            println!("Create an access token in your Settings -> Applications");
             println!(
                 "Then set a {} environment variable with the value",
-                style(&self.env_var).green()
+                style(self.env_var).green()
             );
             return false;
         }
-        if self.name.ends_with('/') {
+        if self.options.name.ends_with('/') {
             println!(
                 "{}",
                 style("Error: Ensure that names do not end in forward slashes").red()
             );
-            println!("You specified: {}", self.name);
+            println!("You specified: {}", self.options.name);
             return false;
         }
         true
     }
 
     fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
-        let gitea_token = env::var(&self.env_var)
-            .with_context(|| format!("Missing {} environment variable", self.env_var))?;
+        let options = self.options;
+        let gitea_token = options.token.resolve(self.env_var, self.url)?;
+        let auth_header = format!("token {}", gitea_token);
+
+        let include_regex_set = create_include_regex_set(&options.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&options.exclude)?;
 
-        let include_regex_set = create_include_regex_set(&self.include)?;
-        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+        let client = HttpClient::new(&options.http, self.url)?;
 
-        let agent = ureq::AgentBuilder::new()
-            .https_only(true)
-            .user_agent(APP_USER_AGENT)
-            .build();
+        let repositories_url = self.repositories_url(&client, &auth_header)?;
 
         let mut page = 1;
         let mut repositories = Vec::new();
 
         loop {
-            let url = format!(
-                "{}/api/v1/users/{}/repos?page={}&limit=50",
-                self.url, self.name, page
-            );
+            let url = format!("{}?page={}&limit=50", repositories_url, page);
 
-            let response = agent
-                .get(&url)
-                .set("Authorization", &format!("token {}", gitea_token))
-                .call()?;
+            let response = client.call(client.get(&url).set("Authorization", &auth_header))?;
 
             let repos: Vec<GiteaRepository> = response.into_json()?;
             if repos.is_empty() {
@@ -147,20 +265,39 @@ impl Provider for GiteaProvider {
             repositories.extend(
                 repos
                     .into_iter()
-                    .filter(|r| !r.archived)
-                    .filter(|r| !self.skip_forks || !r.fork)
+                    .filter(|r| !options.skip_forks || !r.fork)
                     .filter(|r| include_regex_set.is_match(&r.full_name))
                     .filter(|r| !exclude_regex_set.is_match(&r.full_name))
-                    .map(|r| {
-                        Repository::new(
-                            format!("{}/{}", self.path, r.full_name),
-                            if self.auth_http {
-                                r.clone_url
+                    .filter(|r| {
+                        options.filter.is_match(
+                            &r.topics,
+                            r.language(),
+                            r.visibility(),
+                            r.updated_at.as_deref(),
+                        )
+                    })
+                    .filter_map(|r| {
+                        let path = options.archived.base_path(self.path, r.archived)?;
+                        let upstream = r.parent.map(|parent| {
+                            if options.auth_http {
+                                parent.clone_url
                             } else {
-                                r.ssh_url
-                            },
-                            Some(r.default_branch),
-                            None,
+                                parent.ssh_url
+                            }
+                        });
+                        Some(
+                            Repository::from_full_name(
+                                &path,
+                                &r.full_name,
+                                if options.auth_http {
+                                    r.clone_url
+                                } else {
+                                    r.ssh_url
+                                },
+                                Some(r.default_branch),
+                                upstream,
+                            )
+                            .with_id(r.id),
                         )
                     }),
             );
@@ -171,3 +308,204 @@ impl Provider for GiteaProvider {
         Ok(repositories)
     }
 }
+
+impl GiteaProvider {
+    fn instance(&self) -> GiteaInstance<'_> {
+        GiteaInstance {
+            options: &self.options,
+            path: &self.path,
+            env_var: &self.env_var,
+            url: &self.url,
+            default_url: DEFAULT_GITEA_URL,
+        }
+    }
+}
+
+impl fmt::Display for GiteaProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.instance().describe(f, "Gitea")
+    }
+}
+
+impl Provider for GiteaProvider {
+    fn correctly_configured(&self) -> bool {
+        self.instance().correctly_configured()
+    }
+
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.options.path_template.as_ref()
+    }
+
+    fn archived(&self) -> Archived {
+        self.options.archived
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.options.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.options.token)
+    }
+
+    fn scope(&self) -> String {
+        self.instance().scope()
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        self.instance().fetch_repositories()
+    }
+}
+
+impl ForgejoProvider {
+    fn instance(&self) -> GiteaInstance<'_> {
+        GiteaInstance {
+            options: &self.options,
+            path: &self.path,
+            env_var: &self.env_var,
+            url: &self.url,
+            default_url: DEFAULT_FORGEJO_URL,
+        }
+    }
+}
+
+impl fmt::Display for ForgejoProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.instance().describe(f, "Forgejo")
+    }
+}
+
+impl Provider for ForgejoProvider {
+    fn correctly_configured(&self) -> bool {
+        self.instance().correctly_configured()
+    }
+
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.options.path_template.as_ref()
+    }
+
+    fn archived(&self) -> Archived {
+        self.options.archived
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.options.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.options.token)
+    }
+
+    fn scope(&self) -> String {
+        self.instance().scope()
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        self.instance().fetch_repositories()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Response, TestServer};
+    use serde_json::json;
+
+    fn repository(id: u64, full_name: &str, parent: Option<&str>) -> serde_json::Value {
+        let urls = |full_name: &str| {
+            json!({
+                "clone_url": format!("https://codeberg.org/{}.git", full_name),
+                "ssh_url": format!("git@codeberg.org:{}.git", full_name),
+            })
+        };
+        let mut repository = json!({
+            "id": id,
+            "full_name": full_name,
+            "default_branch": "main",
+            "archived": false,
+            "fork": parent.is_some(),
+            "parent": parent.map(urls),
+            "private": false,
+        });
+        repository
+            .as_object_mut()
+            .unwrap()
+            .extend(urls(full_name).as_object().unwrap().clone());
+        repository
+    }
+
+    fn server() -> TestServer {
+        TestServer::start(|request| {
+            let body = match request.target.as_str() {
+                "/api/v1/orgs/acme" => json!({"id": 1, "username": "acme"}),
+                "/api/v1/orgs/acme/repos?page=1&limit=50" => json!([
+                    repository(1, "acme/api", None),
+                    repository(2, "acme/api-fork", Some("upstream/api")),
+                ]),
+                "/api/v1/orgs/acme/repos?page=2&limit=50" => json!([]),
+                _ => return None,
+            };
+            Some(Response::json(body))
+        })
+    }
+
+    fn provider(server: &TestServer) -> ForgejoProvider {
+        ForgejoProvider {
+            options: GiteaOptions {
+                name: "acme".to_string(),
+                token: TokenSource {
+                    token_command: Some("echo secret".to_string()),
+                    ..Default::default()
+                },
+                http: HttpOptions {
+                    allow_insecure_http: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            path: "forgejo".to_string(),
+            env_var: default_forgejo_env_var(),
+            url: server.url.clone(),
+        }
+    }
+
+    #[test]
+    fn test_fetch_repositories() {
+        let server = server();
+        let mut provider = provider(&server);
+        let repositories = provider.fetch_repositories().unwrap();
+
+        let summary: Vec<_> = repositories
+            .iter()
+            .map(|r| (r.name().as_str(), r.url(), r.upstream.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("forgejo/acme/api", "git@codeberg.org:acme/api.git", None),
+                (
+                    "forgejo/acme/api-fork",
+                    "git@codeberg.org:acme/api-fork.git",
+                    Some("git@codeberg.org:upstream/api.git")
+                ),
+            ]
+        );
+        assert!(server
+            .requests()
+            .iter()
+            .all(|request| request.header("Authorization") == Some("token secret")));
+
+        // The upstream uses the same protocol as the fork
+        provider.options.auth_http = true;
+        let repositories = provider.fetch_repositories().unwrap();
+        assert_eq!(
+            repositories[1].upstream.as_deref(),
+            Some("https://codeberg.org/upstream/api.git")
+        );
+
+        provider.options.skip_forks = true;
+        let repositories = provider.fetch_repositories().unwrap();
+        let names: Vec<_> = repositories.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["forgejo/acme/api"]);
+    }
+}
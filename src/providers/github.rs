@@ -1,19 +1,21 @@
 use crate::providers::{
-    create_exclude_regex_set, create_include_regex_set, Provider, APP_USER_AGENT,
+    create_exclude_regex_set, create_include_regex_set, instance_scope, Archived, HttpClient,
+    HttpOptions, MetadataFilter, PathTemplate, Provider, TokenSource, Visibility,
 };
 use crate::repository::Repository;
 use anyhow::{bail, Context};
 use console::style;
 use graphql_client::{GraphQLQuery, Response};
+use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::env;
 use std::fmt;
 
 // See https://github.com/graphql-rust/graphql-client/blob/master/graphql_client/tests/custom_scalars.rs#L6
 type GitSSHRemote = String;
 #[allow(clippy::upper_case_acronyms)]
 type URI = String;
+type DateTime = String;
 
 #[derive(GraphQLQuery)]
 #[graphql(
@@ -23,6 +25,95 @@ type URI = String;
 )]
 pub struct Repositories;
 
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/providers/graphql/github/schema.graphql",
+    query_path = "src/providers/graphql/github/starred.graphql",
+    response_derives = "Debug"
+)]
+pub struct StarredRepositories;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/providers/graphql/github/schema.graphql",
+    query_path = "src/providers/graphql/github/team.graphql",
+    response_derives = "Debug"
+)]
+pub struct TeamRepositories;
+
+// Each query generates its own types, so we convert them into these before processing them.
+struct RepositoryNode {
+    id: String,
+    name_with_owner: String,
+    url: URI,
+    ssh_url: GitSSHRemote,
+    is_archived: bool,
+    is_fork: bool,
+    topics: Vec<String>,
+    language: Option<String>,
+    visibility: Visibility,
+    pushed_at: Option<DateTime>,
+    default_branch: Option<String>,
+    upstream: Option<GitSSHRemote>,
+}
+
+// The queries select the same fields, so their types only differ by name.
+macro_rules! impl_from_query {
+    ($query:ident, $nodes:ident) => {
+        impl From<$query::$nodes> for RepositoryNode {
+            fn from(item: $query::$nodes) -> Self {
+                Self {
+                    id: item.id,
+                    name_with_owner: item.name_with_owner,
+                    url: item.url,
+                    ssh_url: item.ssh_url,
+                    is_archived: item.is_archived,
+                    is_fork: item.is_fork,
+                    topics: item
+                        .repository_topics
+                        .nodes
+                        .unwrap_or_default()
+                        .into_iter()
+                        .flatten()
+                        .map(|node| node.topic.name)
+                        .collect(),
+                    language: item.primary_language.map(|language| language.name),
+                    visibility: item.visibility.into(),
+                    pushed_at: item.pushed_at,
+                    default_branch: item.default_branch_ref.map(|branch| branch.name),
+                    upstream: item.parent.map(|parent| parent.ssh_url),
+                }
+            }
+        }
+
+        impl From<$query::RepositoryVisibility> for Visibility {
+            fn from(item: $query::RepositoryVisibility) -> Self {
+                match item {
+                    $query::RepositoryVisibility::PUBLIC => Self::Public,
+                    $query::RepositoryVisibility::INTERNAL => Self::Internal,
+                    _ => Self::Private,
+                }
+            }
+        }
+    };
+}
+
+impl_from_query!(repositories, RepositoriesRepositoryOwnerRepositoriesNodes);
+impl_from_query!(
+    starred_repositories,
+    StarredRepositoriesUserStarredRepositoriesNodes
+);
+impl_from_query!(
+    team_repositories,
+    TeamRepositoriesOrganizationTeamRepositoriesNodes
+);
+
+struct RepositoryPage {
+    nodes: Vec<RepositoryNode>,
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
 fn default_env_var() -> String {
     String::from("GITHUB_TOKEN")
 }
@@ -42,11 +133,24 @@ pub struct GithubProvider {
     #[arg(long = "path", default_value = "github")]
     /// Clone repositories to a specific base path
     path: String,
+
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
     #[arg(long = "env-name", short = 'e', default_value = "GITHUB_TOKEN")]
     #[serde(default = "default_env_var")]
     /// Environment variable containing the auth token
     env_var: String,
 
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    http: HttpOptions,
+
     #[arg(long = "skip-forks")]
     #[serde(default)]
     /// Don't clone forked repositories
@@ -69,6 +173,26 @@ pub struct GithubProvider {
     /// includes the user or organisation name.
     exclude: Vec<String>,
 
+    #[arg(long = "archived", value_enum, default_value_t)]
+    #[serde(default)]
+    /// Skip archived repositories, include them, or clone them to a separate `<path>-archived`
+    /// directory
+    archived: Archived,
+
+    #[arg(long = "starred")]
+    #[serde(default)]
+    /// Clone the repositories starred by the user instead of the repositories they own
+    starred: bool,
+
+    #[arg(long = "team")]
+    #[serde(default)]
+    /// Only clone the repositories this team of the organisation has access to, by team slug
+    team: Option<String>,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    filter: MetadataFilter,
+
     #[serde(default = "public_github_url")]
     #[arg(long = "url", default_value = DEFAULT_GITHUB_URL)]
     /// Github instance URL, if using Github Enterprise this should be
@@ -78,53 +202,226 @@ pub struct GithubProvider {
 
 impl fmt::Display for GithubProvider {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (description, name) = match (&self.team, self.starred) {
+            (Some(team), _) => ("Github team", format!("{}/{}", self.name, team)),
+            (None, true) => ("Github repositories starred by", self.name.clone()),
+            (None, false) => ("Github user/org", self.name.clone()),
+        };
         write!(
             f,
-            "Github user/org {} in directory {}, using the token stored in {}",
-            style(&self.name.to_lowercase()).green(),
+            "{} {} in directory {}, using the token from {}",
+            description,
+            style(name.to_lowercase()).green(),
             style(&self.path.to_lowercase()).green(),
-            style(&self.env_var).green(),
+            style(self.token.describe(&self.env_var)).green(),
         )
     }
 }
 
 impl GithubProvider {
-    fn parse_repo(
-        &self,
-        path: &str,
-        repo: &repositories::RepositoriesRepositoryOwnerRepositoriesNodes,
-    ) -> Repository {
-        let default_branch = repo
-            .default_branch_ref
-            .as_ref()
-            .map(|branch| branch.name.clone());
-        let upstream = repo.parent.as_ref().map(|parent| parent.ssh_url.clone());
-
-        Repository::new(
-            format!("{}/{}", path, repo.name_with_owner.clone()),
+    fn parse_repo(&self, path: &str, repo: RepositoryNode) -> Repository {
+        Repository::from_full_name(
+            path,
+            &repo.name_with_owner,
             if self.auth_http {
-                repo.url.clone()
+                repo.url
             } else {
-                repo.ssh_url.clone()
+                repo.ssh_url
             },
-            default_branch,
-            upstream,
+            repo.default_branch,
+            repo.upstream,
         )
+        .with_id(repo.id)
+    }
+
+    fn query<Q: GraphQLQuery>(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        variables: Q::Variables,
+    ) -> anyhow::Result<Q::ResponseData> {
+        let q = Q::build_query(variables);
+        let res = client.send_json(
+            client.post(&self.url).set("Authorization", auth_header),
+            json!(&q),
+        );
+
+        let res = match res {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, response)) => match response.into_string() {
+                Ok(resp) => {
+                    bail!("Got status code {status}. Body: {resp}")
+                }
+                Err(e) => {
+                    bail!("Got status code {status}. Error reading body: {e}")
+                }
+            },
+            Err(e) => return Err(e.into()),
+        };
+
+        let body = res.into_string()?;
+        let response_data: Response<Q::ResponseData> = serde_json::from_str(&body)?;
+
+        if let Some(errors) = response_data.errors {
+            let total_errors = errors.len();
+            let combined_errors: Vec<_> = errors.into_iter().map(|e| e.message).collect();
+            let combined_message = combined_errors.join("\n");
+            bail!(
+                "Received {} errors. Errors:\n{}",
+                total_errors,
+                combined_message
+            );
+        }
+
+        response_data
+            .data
+            .with_context(|| format!("Invalid response from GitHub: {}", body))
+    }
+
+    /// Fetch a page of the repositories owned by the user or organisation
+    fn fetch_owned_page(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        after: Option<String>,
+    ) -> anyhow::Result<RepositoryPage> {
+        // include_forks needs to be None instead of true, as the graphql parameter has three
+        // states: false - no forks, true - only forks, none - all repositories.
+        let include_forks: Option<bool> = if self.skip_forks { Some(false) } else { None };
+
+        let data = self.query::<Repositories>(
+            client,
+            auth_header,
+            repositories::Variables {
+                login: self.name.to_lowercase(),
+                include_forks,
+                after,
+            },
+        )?;
+        let repositories = data
+            .repository_owner
+            .with_context(|| format!("GitHub user/org {} could not be found", self.name))?
+            .repositories;
+
+        Ok(RepositoryPage {
+            nodes: repositories
+                .nodes
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .map(RepositoryNode::from)
+                .collect(),
+            has_next_page: repositories.page_info.has_next_page,
+            end_cursor: repositories.page_info.end_cursor,
+        })
+    }
+
+    /// Fetch a page of the repositories starred by the user
+    fn fetch_starred_page(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        after: Option<String>,
+    ) -> anyhow::Result<RepositoryPage> {
+        let data = self.query::<StarredRepositories>(
+            client,
+            auth_header,
+            starred_repositories::Variables {
+                login: self.name.to_lowercase(),
+                after,
+            },
+        )?;
+        let repositories = data
+            .user
+            .with_context(|| format!("GitHub user {} could not be found", self.name))?
+            .starred_repositories;
+
+        Ok(RepositoryPage {
+            nodes: repositories
+                .nodes
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .map(RepositoryNode::from)
+                .collect(),
+            has_next_page: repositories.page_info.has_next_page,
+            end_cursor: repositories.page_info.end_cursor,
+        })
+    }
+
+    /// Fetch a page of the repositories a team of the organisation has access to
+    fn fetch_team_page(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        team: &str,
+        after: Option<String>,
+    ) -> anyhow::Result<RepositoryPage> {
+        let data = self.query::<TeamRepositories>(
+            client,
+            auth_header,
+            team_repositories::Variables {
+                login: self.name.to_lowercase(),
+                team: team.to_string(),
+                after,
+            },
+        )?;
+        let repositories = data
+            .organization
+            .with_context(|| format!("GitHub organisation {} could not be found", self.name))?
+            .team
+            .with_context(|| {
+                format!(
+                    "GitHub team {} could not be found in {}. Are you sure you have access?",
+                    team, self.name
+                )
+            })?
+            .repositories;
+
+        Ok(RepositoryPage {
+            nodes: repositories
+                .nodes
+                .unwrap_or_default()
+                .into_iter()
+                .flatten()
+                .map(RepositoryNode::from)
+                .collect(),
+            has_next_page: repositories.page_info.has_next_page,
+            end_cursor: repositories.page_info.end_cursor,
+        })
+    }
+
+    fn filter_page(
+        &self,
+        page: RepositoryPage,
+        include_regex_set: &RegexSet,
+        exclude_regex_set: &RegexSet,
+    ) -> Vec<Repository> {
+        page.nodes
+            .into_iter()
+            .filter(|r| !self.skip_forks || !r.is_fork)
+            .filter(|r| include_regex_set.is_match(&r.name_with_owner))
+            .filter(|r| !exclude_regex_set.is_match(&r.name_with_owner))
+            .filter(|r| {
+                self.filter.is_match(
+                    &r.topics,
+                    r.language.as_deref(),
+                    r.visibility,
+                    r.pushed_at.as_deref(),
+                )
+            })
+            .filter_map(|repo| {
+                let path = self.archived.base_path(&self.path, repo.is_archived)?;
+                Some(self.parse_repo(&path, repo))
+            })
+            .collect()
     }
 }
 
 impl Provider for GithubProvider {
     fn correctly_configured(&self) -> bool {
-        let token = env::var(&self.env_var);
-        if token.is_err() {
-            println!(
-                "{}",
-                style(format!(
-                    "Error: {} environment variable is not defined",
-                    self.env_var
-                ))
-                .red()
-            );
+        if let Err(e) = self.token.resolve(&self.env_var, &self.url) {
+            println!("{}", style(format!("Error: {:#}", e)).red());
             if self.url == public_github_url() {
                 println!(
                     "Create a personal access token here: {}",
@@ -151,12 +448,45 @@ impl Provider for GithubProvider {
             println!("You specified: {}", self.name);
             return false;
         }
+        if self.starred && self.team.is_some() {
+            println!(
+                "{}",
+                style("Error: Starred repositories cannot be filtered by team").red()
+            );
+            return false;
+        }
         true
     }
 
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    fn archived(&self) -> Archived {
+        self.archived
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.token)
+    }
+
+    fn scope(&self) -> String {
+        let mut name = self.name.clone();
+        if self.starred {
+            name.push_str("/stars");
+        }
+        if let Some(team) = &self.team {
+            name.push_str(&format!("/teams/{}", team));
+        }
+        instance_scope(&self.url, DEFAULT_GITHUB_URL, &name)
+    }
+
     fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
-        let github_token = env::var(&self.env_var)
-            .with_context(|| format!("Missing {} environment variable", self.env_var))?;
+        let github_token = self.token.resolve(&self.env_var, &self.url)?;
 
         let auth_header = match github_token.as_str() {
             "none" => "none".to_string(),
@@ -172,78 +502,74 @@ impl Provider for GithubProvider {
         let include_regex_set = create_include_regex_set(&self.include)?;
         let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
 
-        // include_forks needs to be None instead of true, as the graphql parameter has three
-        // states: false - no forks, true - only forks, none - all repositories.
-        let include_forks: Option<bool> = if self.skip_forks { Some(false) } else { None };
-
-        let agent = ureq::AgentBuilder::new()
-            .https_only(true)
-            .user_agent(APP_USER_AGENT)
-            .build();
+        let client = HttpClient::new(&self.http, &self.url)?;
 
         loop {
-            let q = Repositories::build_query(repositories::Variables {
-                login: self.name.to_lowercase(),
-                include_forks,
-                after,
-            });
-            let res = agent
-                .post(&self.url)
-                .set("Authorization", &auth_header)
-                .send_json(json!(&q));
-
-            let res = match res {
-                Ok(response) => response,
-                Err(ureq::Error::Status(status, response)) => match response.into_string() {
-                    Ok(resp) => {
-                        bail!("Got status code {status}. Body: {resp}")
-                    }
-                    Err(e) => {
-                        bail!("Got status code {status}. Error reading body: {e}")
-                    }
-                },
-                Err(e) => return Err(e.into()),
+            let page = match &self.team {
+                Some(team) => self.fetch_team_page(&client, &auth_header, team, after)?,
+                None if self.starred => self.fetch_starred_page(&client, &auth_header, after)?,
+                None => self.fetch_owned_page(&client, &auth_header, after)?,
             };
+            let has_next_page = page.has_next_page;
+            after = page.end_cursor.clone();
 
-            let body = res.into_string()?;
-            let response_data: Response<repositories::ResponseData> = serde_json::from_str(&body)?;
-
-            if let Some(errors) = response_data.errors {
-                let total_errors = errors.len();
-                let combined_errors: Vec<_> = errors.into_iter().map(|e| e.message).collect();
-                let combined_message = combined_errors.join("\n");
-                bail!(
-                    "Received {} errors. Errors:\n{}",
-                    total_errors,
-                    combined_message
-                );
-            }
+            repositories.extend(self.filter_page(page, &include_regex_set, &exclude_regex_set));
 
-            let response_repositories = response_data
-                .data
-                .with_context(|| format!("Invalid response from GitHub: {}", body))?
-                .repository_owner
-                .with_context(|| format!("Invalid response from GitHub: {}", body))?
-                .repositories;
-
-            repositories.extend(
-                response_repositories
-                    .nodes
-                    .unwrap()
-                    .iter()
-                    .map(|r| r.as_ref().unwrap())
-                    .filter(|r| !r.is_archived)
-                    .filter(|r| include_regex_set.is_match(&r.name_with_owner))
-                    .filter(|r| !exclude_regex_set.is_match(&r.name_with_owner))
-                    .map(|repo| self.parse_repo(&self.path, repo)),
-            );
-
-            if !response_repositories.page_info.has_next_page {
+            if !has_next_page {
                 break;
             }
-            after = response_repositories.page_info.end_cursor;
         }
 
         Ok(repositories)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE: &str = r#"{
+        "id": "R_kgDOabc123",
+        "nameWithOwner": "acme/api",
+        "url": "https://github.com/acme/api",
+        "sshUrl": "git@github.com:acme/api.git",
+        "isArchived": false,
+        "isFork": true,
+        "pushedAt": "2024-05-01T12:00:00Z",
+        "visibility": "INTERNAL",
+        "primaryLanguage": {"name": "Rust"},
+        "repositoryTopics": {"nodes": [{"topic": {"name": "backend"}}]},
+        "defaultBranchRef": {"name": "main"},
+        "parent": {"url": "https://github.com/other/api", "sshUrl": "git@github.com:other/api.git"}
+    }"#;
+
+    fn summary(node: RepositoryNode) -> (String, bool, Vec<String>, Option<String>, Visibility) {
+        (
+            node.name_with_owner,
+            node.is_fork,
+            node.topics,
+            node.upstream,
+            node.visibility,
+        )
+    }
+
+    #[test]
+    fn test_repository_node() {
+        let expected = (
+            "acme/api".to_string(),
+            true,
+            vec!["backend".to_string()],
+            Some("git@github.com:other/api.git".to_string()),
+            Visibility::Internal,
+        );
+        let owner: repositories::RepositoriesRepositoryOwnerRepositoriesNodes =
+            serde_json::from_str(NODE).unwrap();
+        assert_eq!(summary(owner.into()), expected);
+        let starred: starred_repositories::StarredRepositoriesUserStarredRepositoriesNodes =
+            serde_json::from_str(NODE).unwrap();
+        assert_eq!(summary(starred.into()), expected);
+        let team: team_repositories::TeamRepositoriesOrganizationTeamRepositoriesNodes =
+            serde_json::from_str(NODE).unwrap();
+        assert_eq!(summary(team.into()), expected);
+    }
+}
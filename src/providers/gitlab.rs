@@ -1,13 +1,15 @@
 use crate::providers::{
-    create_exclude_regex_set, create_include_regex_set, Provider, APP_USER_AGENT,
+    create_exclude_regex_set, create_include_regex_set, instance_scope, Archived, HttpClient,
+    HttpOptions, MetadataFilter, PathTemplate, Provider, TokenSource, Visibility,
 };
 use crate::repository::Repository;
 use anyhow::{anyhow, Context};
 use console::style;
 use graphql_client::{GraphQLQuery, Response};
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::env;
+use std::collections::HashMap;
 use std::fmt;
 
 // GraphQL queries we use to fetch user and group repositories.
@@ -16,6 +18,9 @@ use std::fmt;
 // So, we don't paginate at all in these queries. I'll fix this once
 // the issue is closed.
 
+// See https://github.com/graphql-rust/graphql-client/blob/master/graphql_client/tests/custom_scalars.rs#L6
+type Time = String;
+
 #[derive(GraphQLQuery)]
 #[graphql(
     schema_path = "src/providers/graphql/gitlab/schema.json",
@@ -25,39 +30,121 @@ use std::fmt;
 pub struct Repositories;
 
 struct ProjectNode {
+    id: String,
     archived: bool,
     full_path: String,
     ssh_url: String,
     http_url: String,
     root_ref: Option<String>,
+    topics: Vec<String>,
+    language: Option<String>,
+    visibility: Visibility,
+    last_activity_at: Option<Time>,
+}
+
+/// A project as returned by the REST API. The GraphQL API doesn't say which project a fork was
+/// forked from, so forks are found with the REST API.
+#[derive(Deserialize, Debug)]
+struct RestProject {
+    path_with_namespace: String,
+    forked_from_project: Option<RestForkedFrom>,
 }
 
-impl From<repositories::RepositoriesGroupProjectsEdgesNode> for ProjectNode {
-    fn from(item: repositories::RepositoriesGroupProjectsEdgesNode) -> Self {
-        Self {
-            archived: item.archived.unwrap(),
+#[derive(Deserialize, Debug)]
+struct RestForkedFrom {
+    ssh_url_to_repo: String,
+    http_url_to_repo: String,
+}
+
+fn parse_visibility(visibility: Option<&str>) -> Visibility {
+    match visibility {
+        Some("public") => Visibility::Public,
+        Some("internal") => Visibility::Internal,
+        _ => Visibility::Private,
+    }
+}
+
+/// Topics are given as a comma separated list
+fn parse_topics(tag_list: Option<&str>) -> Vec<String> {
+    tag_list
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|topic| !topic.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// GitLab doesn't have a primary language, so we use the most used one. Languages are given
+/// with their share of the repository.
+fn most_used_language(languages: HashMap<String, f64>) -> Option<String> {
+    languages
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(name, _)| name)
+}
+
+impl TryFrom<repositories::RepositoriesGroupProjectsEdgesNode> for ProjectNode {
+    type Error = anyhow::Error;
+
+    fn try_from(item: repositories::RepositoriesGroupProjectsEdgesNode) -> anyhow::Result<Self> {
+        let full_path = item.full_path;
+        Ok(Self {
+            id: item.id,
+            archived: item
+                .archived
+                .with_context(|| format!("Project {} has no archived state", full_path))?,
+            topics: parse_topics(item.tag_list.as_deref()),
+            // Fetched separately, only when filtering by language
+            language: None,
+            visibility: parse_visibility(item.visibility.as_deref()),
+            last_activity_at: item.last_activity_at,
             root_ref: item.repository.and_then(|r| r.root_ref),
-            ssh_url: item.ssh_url_to_repo.expect("Unknown SSH URL"),
-            http_url: item.http_url_to_repo.expect("Unknown HTTP URL"),
-            full_path: item.full_path,
-        }
+            ssh_url: item
+                .ssh_url_to_repo
+                .with_context(|| format!("Project {} has no SSH URL", full_path))?,
+            http_url: item
+                .http_url_to_repo
+                .with_context(|| format!("Project {} has no HTTP URL", full_path))?,
+            full_path,
+        })
     }
 }
 
-impl From<repositories::RepositoriesNamespaceProjectsEdgesNode> for ProjectNode {
-    fn from(item: repositories::RepositoriesNamespaceProjectsEdgesNode) -> Self {
-        Self {
-            archived: item.archived.unwrap(),
+impl TryFrom<repositories::RepositoriesNamespaceProjectsEdgesNode> for ProjectNode {
+    type Error = anyhow::Error;
+
+    fn try_from(
+        item: repositories::RepositoriesNamespaceProjectsEdgesNode,
+    ) -> anyhow::Result<Self> {
+        let full_path = item.full_path;
+        Ok(Self {
+            id: item.id,
+            archived: item
+                .archived
+                .with_context(|| format!("Project {} has no archived state", full_path))?,
+            topics: parse_topics(item.tag_list.as_deref()),
+            // Fetched separately, only when filtering by language
+            language: None,
+            visibility: parse_visibility(item.visibility.as_deref()),
+            last_activity_at: item.last_activity_at,
             root_ref: item.repository.and_then(|r| r.root_ref),
-            ssh_url: item.ssh_url_to_repo.expect("Unknown SSH URL"),
-            http_url: item.http_url_to_repo.expect("Unknown HTTP URL"),
-            full_path: item.full_path,
-        }
+            ssh_url: item
+                .ssh_url_to_repo
+                .with_context(|| format!("Project {} has no SSH URL", full_path))?,
+            http_url: item
+                .http_url_to_repo
+                .with_context(|| format!("Project {} has no HTTP URL", full_path))?,
+            full_path,
+        })
     }
 }
 
 static DEFAULT_GITLAB_URL: &str = "https://gitlab.com";
 
+// The maximum number of projects per page of the REST API
+const REST_PAGE_SIZE: usize = 100;
+
 fn public_gitlab_url() -> String {
     DEFAULT_GITLAB_URL.to_string()
 }
@@ -79,11 +166,29 @@ pub struct GitlabProvider {
     #[arg(long = "path", default_value = "gitlab")]
     /// Clone repos to a specific path
     path: String,
+
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
     #[arg(long = "env-name", short = 'e', default_value = "GITLAB_TOKEN")]
     #[serde(default = "default_env_var")]
     /// Environment variable containing the auth token
     env_var: String,
 
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    http: HttpOptions,
+
+    #[arg(long = "skip-forks")]
+    #[serde(default)]
+    /// Don't clone forked repositories
+    skip_forks: bool,
+
     #[arg(long = "include")]
     #[serde(default)]
     /// Only clone repositories that match these regular expressions. The repository name
@@ -100,39 +205,84 @@ pub struct GitlabProvider {
     /// Don't clone repositories that match these regular expressions. The repository name
     /// includes the user or organisation name.
     exclude: Vec<String>,
-    // Currently does not work.
-    // https://gitlab.com/gitlab-org/gitlab/issues/121595
-    //    #[arg(long = "skip-forks")]
-    //    #[arg(about = "Don't clone forked repositories")]
-    //    #[serde(default = "default_forks")]
-    //    skip_forks: bool,
+
+    #[arg(long = "archived", value_enum, default_value_t)]
+    #[serde(default)]
+    /// Skip archived repositories, include them, or clone them to a separate `<path>-archived`
+    /// directory
+    archived: Archived,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    filter: MetadataFilter,
 }
 
 impl fmt::Display for GitlabProvider {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Gitlab user/group {} at {} in directory {}, using the token stored in {}",
+            "Gitlab user/group {} at {} in directory {}, using the token from {}",
             style(&self.name.to_lowercase()).green(),
             style(&self.url).green(),
             style(&self.path).green(),
-            style(&self.env_var).green(),
+            style(self.token.describe(&self.env_var)).green(),
         )
     }
 }
 
+impl GitlabProvider {
+    /// The projects that were forked from another one, by full path, with the project they were
+    /// forked from. `projects_path` is `groups/<group>` or `users/<user>`.
+    fn fetch_forks(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        projects_path: &str,
+    ) -> anyhow::Result<HashMap<String, RestForkedFrom>> {
+        let mut forks = HashMap::new();
+        for page in 1.. {
+            let url = format!(
+                "{}/api/v4/{}/projects?include_subgroups=true&per_page={}&page={}",
+                self.url, projects_path, REST_PAGE_SIZE, page
+            );
+            let projects: Vec<RestProject> = client
+                .call(client.get(&url).set("Authorization", auth_header))?
+                .into_json()?;
+            let last_page = projects.len() < REST_PAGE_SIZE;
+            forks.extend(projects.into_iter().filter_map(|project| {
+                Some((project.path_with_namespace, project.forked_from_project?))
+            }));
+            if last_page {
+                break;
+            }
+        }
+        Ok(forks)
+    }
+
+    /// The most used language of a project. The GraphQL API doesn't return languages, so this
+    /// needs one request per project.
+    fn fetch_language(
+        &self,
+        client: &HttpClient,
+        auth_header: &str,
+        full_path: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let url = format!(
+            "{}/api/v4/projects/{}/languages",
+            self.url,
+            full_path.replace('/', "%2F")
+        );
+        let languages: HashMap<String, f64> = client
+            .call(client.get(&url).set("Authorization", auth_header))?
+            .into_json()?;
+        Ok(most_used_language(languages))
+    }
+}
+
 impl Provider for GitlabProvider {
     fn correctly_configured(&self) -> bool {
-        let token = env::var(&self.env_var);
-        if token.is_err() {
-            println!(
-                "{}",
-                style(format!(
-                    "Error: {} environment variable is not defined",
-                    self.env_var
-                ))
-                .red()
-            );
+        if let Err(e) = self.token.resolve(&self.env_var, &self.url) {
+            println!("{}", style(format!("Error: {:#}", e)).red());
             println!("Create a personal access token here:");
             println!("{}/profile/personal_access_tokens", self.url);
             println!(
@@ -151,64 +301,100 @@ impl Provider for GitlabProvider {
         }
         true
     }
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    fn archived(&self) -> Archived {
+        self.archived
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.token)
+    }
+
+    fn scope(&self) -> String {
+        instance_scope(&self.url, DEFAULT_GITLAB_URL, &self.name)
+    }
+
     fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
-        let gitlab_token = env::var(&self.env_var)
-            .with_context(|| format!("Missing {} environment variable", self.env_var))?;
-        let mut repositories = vec![];
+        let gitlab_token = self.token.resolve(&self.env_var, &self.url)?;
+        let auth_header = format!("Bearer {}", gitlab_token);
+        let mut projects = vec![];
         let mut after = Some("".to_string());
         let name = self.name.to_string().to_lowercase();
+        // The REST API lists the projects of groups and users with different endpoints
+        let mut rest_projects_path;
 
         let include_regex_set = create_include_regex_set(&self.include)?;
         let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
 
-        let agent = ureq::AgentBuilder::new()
-            .https_only(true)
-            .user_agent(APP_USER_AGENT)
-            .build();
+        let client = HttpClient::new(&self.http, &self.url)?;
 
         loop {
             let q = Repositories::build_query(repositories::Variables {
                 name: name.clone(),
                 after,
             });
-            let res = agent
-                .post(format!("{}/api/graphql", self.url).as_str())
-                .set("Authorization", format!("Bearer {}", gitlab_token).as_str())
-                .set("Content-Type", "application/json")
-                .send_json(json!(&q))?;
-            let json = res.into_json()?;
+            let res = client.send_json(
+                client
+                    .post(format!("{}/api/graphql", self.url).as_str())
+                    .set("Authorization", &auth_header)
+                    .set("Content-Type", "application/json"),
+                json!(&q),
+            )?;
+            let body = res.into_string()?;
 
-            let response_body: Response<repositories::ResponseData> = serde_json::from_value(json)?;
-            let data = response_body.data.expect("Missing data");
+            let response_body: Response<repositories::ResponseData> = serde_json::from_str(&body)?;
+            if let Some(errors) = response_body.errors {
+                let combined_errors: Vec<_> = errors.into_iter().map(|e| e.message).collect();
+                anyhow::bail!(
+                    "Received {} errors. Errors:\n{}",
+                    combined_errors.len(),
+                    combined_errors.join("\n")
+                );
+            }
+            let data = response_body
+                .data
+                .with_context(|| format!("Invalid response from GitLab: {}", body))?;
 
-            let temp_repositories: Vec<ProjectNode>;
             // This is annoying but I'm still not sure how to unify it.
-            if data.group.is_some() {
-                let group_data = data.group.expect("Missing group").projects;
-                temp_repositories = group_data
-                    .edges
-                    .expect("missing edges")
-                    .into_iter()
-                    // Some(T) -> T
-                    .flatten()
-                    // Extract the node, which is also Some(T)
-                    .filter_map(|x| x.node)
-                    .map(ProjectNode::from)
-                    .collect();
+            if let Some(group) = data.group {
+                let group_data = group.projects;
+                projects.extend(
+                    group_data
+                        .edges
+                        .context("Missing edges in the response from GitLab")?
+                        .into_iter()
+                        // Some(T) -> T
+                        .flatten()
+                        // Extract the node, which is also Some(T)
+                        .filter_map(|x| x.node)
+                        .map(ProjectNode::try_from)
+                        .collect::<anyhow::Result<Vec<_>>>()?,
+                );
                 after = group_data.page_info.end_cursor;
-            } else if data.namespace.is_some() {
-                let namespace_data = data.namespace.expect("Missing namespace").projects;
-                temp_repositories = namespace_data
-                    .edges
-                    .expect("missing edges")
-                    .into_iter()
-                    // Some(T) -> T
-                    .flatten()
-                    // Extract the node, which is also Some(T)
-                    .filter_map(|x| x.node)
-                    .map(ProjectNode::from)
-                    .collect();
+                rest_projects_path = format!("groups/{}", name.replace('/', "%2F"));
+            } else if let Some(namespace) = data.namespace {
+                let namespace_data = namespace.projects;
+                projects.extend(
+                    namespace_data
+                        .edges
+                        .context("Missing edges in the response from GitLab")?
+                        .into_iter()
+                        // Some(T) -> T
+                        .flatten()
+                        // Extract the node, which is also Some(T)
+                        .filter_map(|x| x.node)
+                        .map(ProjectNode::try_from)
+                        .collect::<anyhow::Result<Vec<_>>>()?,
+                );
                 after = namespace_data.page_info.end_cursor;
+                rest_projects_path = format!("users/{}", name);
             } else {
                 return Err(anyhow!(
                     "Gitlab group/user {} could not be found. Are you sure you have access?",
@@ -216,30 +402,240 @@ impl Provider for GitlabProvider {
                 ));
             }
 
-            repositories.extend(
-                temp_repositories
-                    .into_iter()
-                    .filter(|r| !r.archived)
-                    .filter(|r| include_regex_set.is_match(&r.full_path))
-                    .filter(|r| !exclude_regex_set.is_match(&r.full_path))
-                    .map(|r| {
-                        Repository::new(
-                            format!("{}/{}", self.path, r.full_path),
-                            if self.auth_http {
-                                r.http_url
-                            } else {
-                                r.ssh_url
-                            },
-                            r.root_ref,
-                            None,
-                        )
-                    }),
-            );
-
             if after.is_none() {
                 break;
             }
         }
-        Ok(repositories)
+
+        let forks = self
+            .fetch_forks(&client, &auth_header, &rest_projects_path)
+            .context("Error fetching forks")?;
+
+        let mut projects: Vec<_> = projects
+            .into_iter()
+            .filter(|r| !self.skip_forks || !forks.contains_key(&r.full_path))
+            .filter(|r| include_regex_set.is_match(&r.full_path))
+            .filter(|r| !exclude_regex_set.is_match(&r.full_path))
+            .collect();
+        if !self.filter.languages.is_empty() {
+            projects.par_iter_mut().try_for_each(|r| {
+                r.language = self
+                    .fetch_language(&client, &auth_header, &r.full_path)
+                    .with_context(|| format!("Error fetching languages of {}", r.full_path))?;
+                anyhow::Ok(())
+            })?;
+        }
+
+        Ok(projects
+            .into_iter()
+            .filter(|r| {
+                self.filter.is_match(
+                    &r.topics,
+                    r.language.as_deref(),
+                    r.visibility,
+                    r.last_activity_at.as_deref(),
+                )
+            })
+            .filter_map(|r| {
+                let path = self.archived.base_path(&self.path, r.archived)?;
+                let upstream = forks.get(&r.full_path).map(|parent| {
+                    if self.auth_http {
+                        parent.http_url_to_repo.clone()
+                    } else {
+                        parent.ssh_url_to_repo.clone()
+                    }
+                });
+                Some(
+                    Repository::from_full_name(
+                        &path,
+                        &r.full_path,
+                        if self.auth_http {
+                            r.http_url
+                        } else {
+                            r.ssh_url
+                        },
+                        r.root_ref,
+                        upstream,
+                    )
+                    .with_id(r.id),
+                )
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Response, TestServer};
+
+    fn project(id: u64, full_path: &str) -> serde_json::Value {
+        json!({"node": {
+            "id": format!("gid://gitlab/Project/{}", id),
+            "archived": false,
+            "fullPath": full_path,
+            "sshUrlToRepo": format!("git@gitlab.com:{}.git", full_path),
+            "httpUrlToRepo": format!("https://gitlab.com/{}.git", full_path),
+            "visibility": "private",
+            "lastActivityAt": "2024-05-01T12:00:00Z",
+            "tagList": "",
+            "repository": {"rootRef": "main"},
+        }})
+    }
+
+    fn rest_project(full_path: &str, forked_from: Option<&str>) -> serde_json::Value {
+        json!({
+            "path_with_namespace": full_path,
+            "forked_from_project": forked_from.map(|full_path| json!({
+                "ssh_url_to_repo": format!("git@gitlab.com:{}.git", full_path),
+                "http_url_to_repo": format!("https://gitlab.com/{}.git", full_path),
+            })),
+        })
+    }
+
+    fn provider(server: &TestServer) -> GitlabProvider {
+        GitlabProvider {
+            name: "acme".to_string(),
+            url: server.url.clone(),
+            path: "gitlab".to_string(),
+            token: TokenSource {
+                token_command: Some("echo secret".to_string()),
+                ..Default::default()
+            },
+            http: HttpOptions {
+                allow_insecure_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn server() -> TestServer {
+        TestServer::start(|request| {
+            if request.method == "GET" {
+                // The forks, listed with the REST API
+                return match request.target.as_str() {
+                    "/api/v4/groups/acme/projects?include_subgroups=true&per_page=100&page=1" => {
+                        Some(Response::json(json!([
+                            rest_project("acme/api", None),
+                            rest_project("acme/api-fork", Some("upstream/api")),
+                        ])))
+                    }
+                    "/api/v4/projects/acme%2Fapi/languages" => {
+                        Some(Response::json(json!({"Rust": 90.5, "Shell": 9.5})))
+                    }
+                    "/api/v4/projects/acme%2Fapi-fork/languages" => {
+                        Some(Response::json(json!({"Go": 100.0})))
+                    }
+                    _ => None,
+                };
+            }
+            if request.method != "POST" || request.target != "/api/graphql" {
+                return None;
+            }
+            let body: serde_json::Value = serde_json::from_str(&request.body).ok()?;
+            let projects = match body["variables"]["after"].as_str() {
+                Some("") => json!({
+                    "edges": [project(1, "acme/api")],
+                    "pageInfo": {"hasNextPage": true, "endCursor": "page-2"},
+                }),
+                Some("page-2") => json!({
+                    "edges": [project(2, "acme/api-fork")],
+                    "pageInfo": {"hasNextPage": false, "endCursor": null},
+                }),
+                _ => return None,
+            };
+            Some(Response::json(json!({
+                "data": {"group": {"projects": projects}, "namespace": null}
+            })))
+        })
+    }
+
+    #[test]
+    fn test_fetch_repositories() {
+        let server = server();
+        let mut provider = provider(&server);
+        let repositories = provider.fetch_repositories().unwrap();
+
+        let summary: Vec<_> = repositories
+            .iter()
+            .map(|r| (r.name().as_str(), r.url(), r.upstream.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("gitlab/acme/api", "git@gitlab.com:acme/api.git", None),
+                (
+                    "gitlab/acme/api-fork",
+                    "git@gitlab.com:acme/api-fork.git",
+                    Some("git@gitlab.com:upstream/api.git")
+                ),
+            ]
+        );
+        assert_eq!(
+            repositories[0].id.as_deref(),
+            Some("gid://gitlab/Project/1")
+        );
+
+        // The upstream uses the same protocol as the fork
+        provider.auth_http = true;
+        let repositories = provider.fetch_repositories().unwrap();
+        assert_eq!(
+            repositories[1].upstream.as_deref(),
+            Some("https://gitlab.com/upstream/api.git")
+        );
+    }
+
+    #[test]
+    fn test_skip_forks() {
+        let server = server();
+        let mut provider = provider(&server);
+        provider.skip_forks = true;
+        let repositories = provider.fetch_repositories().unwrap();
+
+        let names: Vec<_> = repositories.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["gitlab/acme/api"]);
+    }
+
+    #[test]
+    fn test_language_filter() {
+        let server = server();
+        let mut provider = provider(&server);
+        provider.fetch_repositories().unwrap();
+        // Languages are only fetched to filter by language
+        assert!(!server
+            .requests()
+            .iter()
+            .any(|request| request.target.ends_with("/languages")));
+
+        provider.filter.languages = vec!["rust".to_string()];
+        let repositories = provider.fetch_repositories().unwrap();
+        let names: Vec<_> = repositories.iter().map(|r| r.name()).collect();
+        assert_eq!(names, ["gitlab/acme/api"]);
+    }
+
+    #[test]
+    fn test_graphql_errors() {
+        let server = TestServer::start(|_| {
+            Some(Response::json(json!({
+                "errors": [{"message": "Field 'unknown' doesn't exist on type 'Project'"}]
+            })))
+        });
+        let error = provider(&server).fetch_repositories().unwrap_err();
+        assert!(error.to_string().contains("Field 'unknown' doesn't exist"));
+    }
+
+    #[test]
+    fn test_parse_topics() {
+        assert_eq!(parse_topics(Some("backend, rust,,")), ["backend", "rust"]);
+        assert!(parse_topics(Some("")).is_empty());
+        assert!(parse_topics(None).is_empty());
+    }
+
+    #[test]
+    fn test_most_used_language() {
+        let languages = HashMap::from([("Shell".to_string(), 12.5), ("Rust".to_string(), 80.1)]);
+        assert_eq!(most_used_language(languages), Some("Rust".to_string()));
+        assert_eq!(most_used_language(HashMap::new()), None);
     }
 }
@@ -0,0 +1,529 @@
+use crate::providers::cache::ResponseCache;
+use crate::providers::APP_USER_AGENT;
+use crate::utils::expand_workspace_path;
+use anyhow::Context;
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static DEFAULT_RETRIES: u32 = 3;
+static DEFAULT_TIMEOUT: u64 = 60;
+
+// Waiting longer than this for a rate limit to reset is worse than failing the provider
+static MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
+fn default_retries() -> u32 {
+    DEFAULT_RETRIES
+}
+
+fn is_default_retries(retries: &u32) -> bool {
+    *retries == DEFAULT_RETRIES
+}
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT
+}
+
+fn is_default_timeout(timeout: &u64) -> bool {
+    *timeout == DEFAULT_TIMEOUT
+}
+
+fn is_zero(value: &u64) -> bool {
+    *value == 0
+}
+
+/// Options of the HTTP client used to talk to the API of a provider
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args)]
+pub struct HttpOptions {
+    #[arg(long = "retries", default_value_t = DEFAULT_RETRIES)]
+    #[serde(
+        default = "default_retries",
+        skip_serializing_if = "is_default_retries"
+    )]
+    /// Number of times requests failing with a transient error are retried
+    pub retries: u32,
+
+    #[arg(long = "timeout", default_value_t = DEFAULT_TIMEOUT)]
+    #[serde(
+        default = "default_timeout",
+        skip_serializing_if = "is_default_timeout"
+    )]
+    /// Timeout of each request, in seconds
+    pub timeout: u64,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    pub connection: ConnectionOptions,
+
+    #[arg(long = "allow-insecure-http")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// Allow plain HTTP URLs, e.g. for a local test instance. Tokens are sent unencrypted.
+    pub allow_insecure_http: bool,
+
+    #[arg(long = "cache-ttl", default_value_t = 0)]
+    #[serde(default, skip_serializing_if = "is_zero")]
+    /// Reuse cached API responses that can't be revalidated, like GraphQL pages, for this many
+    /// seconds instead of fetching them again. They are not checked for changes meanwhile.
+    pub cache_ttl: u64,
+
+    #[arg(skip)]
+    #[serde(skip)]
+    /// Where API responses are cached, set when locking a workspace
+    pub cache_dir: Option<PathBuf>,
+
+    #[arg(skip)]
+    #[serde(skip)]
+    /// The workspace directory, which a relative `ca_bundle` is relative to
+    pub workspace: PathBuf,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+            timeout: DEFAULT_TIMEOUT,
+            connection: ConnectionOptions::default(),
+            allow_insecure_http: false,
+            cache_ttl: 0,
+            cache_dir: None,
+            workspace: PathBuf::new(),
+        }
+    }
+}
+
+/// How to reach the API of a provider. These can also be set for all the providers of a
+/// configuration file, in its `[http]` table.
+#[derive(
+    Deserialize, Serialize, Default, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args,
+)]
+pub struct ConnectionOptions {
+    #[arg(long = "proxy")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Send requests through this proxy, e.g. `http://proxy.company.com:8080`. Defaults to the
+    /// HTTPS_PROXY environment variable, unless the host is listed in NO_PROXY.
+    pub proxy: Option<String>,
+
+    #[arg(long = "ca-bundle")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Also trust the certificates of this PEM file, e.g. for a self-hosted provider
+    pub ca_bundle: Option<PathBuf>,
+}
+
+impl ConnectionOptions {
+    /// Fill the options that are not set with the ones of `defaults`
+    pub fn merge_defaults(&mut self, defaults: &ConnectionOptions) {
+        if self.proxy.is_none() {
+            self.proxy.clone_from(&defaults.proxy);
+        }
+        if self.ca_bundle.is_none() {
+            self.ca_bundle.clone_from(&defaults.ca_bundle);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+fn is_plain_http(url: &str) -> bool {
+    url.get(..7)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://"))
+}
+
+/// The host of a URL, without the port or credentials
+fn url_host(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+    match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    }
+}
+
+/// Returns true if `host` is matched by a comma separated NO_PROXY list. Entries match the host
+/// itself and its subdomains, and `*` matches every host.
+fn no_proxy_matches(no_proxy: &str, host: &str) -> bool {
+    let host = host.to_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            let entry = entry.split(':').next().unwrap_or_default();
+            let domain = entry.trim_start_matches("*.").trim_start_matches('.');
+            entry == "*" || host == domain || host.ends_with(&format!(".{}", domain))
+        })
+}
+
+/// The proxy configured in the environment for `url`, if any
+fn proxy_from_env(url: &str, var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    let var = |name: &str| {
+        var(name)
+            .or_else(|| var(&name.to_lowercase()))
+            .filter(|value| !value.trim().is_empty())
+    };
+    if var("NO_PROXY").is_some_and(|no_proxy| no_proxy_matches(&no_proxy, url_host(url))) {
+        return None;
+    }
+    // Plain HTTP URLs are allowed by allow_insecure_http, and use the proxy for HTTP
+    let scheme_proxy = if is_plain_http(url) {
+        "HTTP_PROXY"
+    } else {
+        "HTTPS_PROXY"
+    };
+    var(scheme_proxy).or_else(|| var("ALL_PROXY"))
+}
+
+/// A TLS configuration trusting the system certificates and the ones of a PEM file
+fn tls_config(path: &Path) -> anyhow::Result<rustls::ClientConfig> {
+    let mut roots = rustls::RootCertStore::empty();
+    let native_certs =
+        rustls_native_certs::load_native_certs().context("Error loading system certificates")?;
+    roots.add_parsable_certificates(native_certs);
+
+    let file = fs::File::open(path)
+        .with_context(|| format!("Cannot read CA bundle {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Error parsing CA bundle {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in CA bundle {}", path.display());
+    }
+    for cert in certs {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid certificate in CA bundle {}", path.display()))?;
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    Ok(rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// Sends requests to the API of a provider, retrying transient failures
+pub struct HttpClient {
+    agent: ureq::Agent,
+    retries: u32,
+    cache: Option<ResponseCache>,
+}
+
+/// How long to wait before retrying a failed request, or None if it shouldn't be retried
+fn retry_delay(error: &ureq::Error, attempt: u32, now: SystemTime) -> Option<Duration> {
+    let backoff = Duration::from_secs(1 << attempt.min(8));
+    let response = match error {
+        ureq::Error::Status(_, response) => response,
+        ureq::Error::Transport(transport) => {
+            return match transport.kind() {
+                ureq::ErrorKind::Dns | ureq::ErrorKind::ConnectionFailed | ureq::ErrorKind::Io => {
+                    Some(backoff)
+                }
+                _ => None,
+            };
+        }
+    };
+
+    let retry_after = response
+        .header("Retry-After")
+        .and_then(|seconds| seconds.trim().parse().ok())
+        .map(Duration::from_secs);
+    // GitHub reports when its rate limit resets, as a UNIX timestamp
+    let rate_limit_reset = match response.header("X-RateLimit-Remaining") {
+        Some("0") => response
+            .header("X-RateLimit-Reset")
+            .and_then(|reset| reset.trim().parse().ok())
+            .map(|reset| {
+                let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+                Duration::from_secs(reset).saturating_sub(now) + Duration::from_secs(1)
+            }),
+        _ => None,
+    };
+
+    let delay = match (response.status(), retry_after.or(rate_limit_reset)) {
+        (403 | 429, Some(delay)) => delay,
+        (429 | 500 | 502 | 503 | 504, delay) => delay.unwrap_or(backoff),
+        _ => return None,
+    };
+    Some(delay).filter(|delay| *delay <= MAX_RETRY_DELAY)
+}
+
+// Errors are returned as they are by ureq, so that callers can match on their status.
+#[allow(clippy::result_large_err)]
+impl HttpClient {
+    /// Create a client for the provider at `url`, which decides whether a proxy is used
+    pub fn new(options: &HttpOptions, url: &str) -> anyhow::Result<Self> {
+        if is_plain_http(url) {
+            if !options.allow_insecure_http {
+                anyhow::bail!(
+                    "{} uses plain HTTP, set allow_insecure_http to send requests to it",
+                    url
+                );
+            }
+            eprintln!(
+                "{}",
+                style(format!(
+                    "Warning: sending requests to {} over plain HTTP, without encryption",
+                    url
+                ))
+                .yellow()
+            );
+        }
+
+        let mut builder = ureq::AgentBuilder::new()
+            .https_only(!options.allow_insecure_http)
+            .user_agent(APP_USER_AGENT)
+            .timeout(Duration::from_secs(options.timeout));
+
+        let connection = &options.connection;
+        let proxy = connection
+            .proxy
+            .clone()
+            .or_else(|| proxy_from_env(url, |name| env::var(name).ok()));
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(
+                ureq::Proxy::new(&proxy).with_context(|| format!("Invalid proxy {}", proxy))?,
+            );
+        }
+        if let Some(ca_bundle) = &connection.ca_bundle {
+            let ca_bundle = options.workspace.join(expand_workspace_path(ca_bundle)?);
+            builder = builder.tls_config(Arc::new(tls_config(&ca_bundle)?));
+        }
+
+        Ok(Self {
+            agent: builder.build(),
+            retries: options.retries,
+            cache: options
+                .cache_dir
+                .clone()
+                .map(|dir| ResponseCache::new(dir, Duration::from_secs(options.cache_ttl))),
+        })
+    }
+
+    pub fn get(&self, url: &str) -> ureq::Request {
+        self.agent.get(url)
+    }
+
+    pub fn post(&self, url: &str) -> ureq::Request {
+        self.agent.post(url)
+    }
+
+    fn retry(
+        &self,
+        send: impl Fn() -> Result<ureq::Response, ureq::Error>,
+    ) -> Result<ureq::Response, ureq::Error> {
+        let mut attempt = 0;
+        loop {
+            match send() {
+                Err(error) if attempt < self.retries => {
+                    match retry_delay(&error, attempt, SystemTime::now()) {
+                        Some(delay) => thread::sleep(delay),
+                        None => return Err(error),
+                    }
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn cached(
+        &self,
+        request: ureq::Request,
+        body: Option<&str>,
+        send: impl FnOnce(ureq::Request) -> Result<ureq::Response, ureq::Error>,
+    ) -> Result<ureq::Response, ureq::Error> {
+        match &self.cache {
+            Some(cache) => cache.send(request, body, send),
+            None => send(request),
+        }
+    }
+
+    /// Send a request without a body
+    pub fn call(&self, request: ureq::Request) -> Result<ureq::Response, ureq::Error> {
+        self.cached(request, None, |request| {
+            self.retry(|| request.clone().call())
+        })
+    }
+
+    /// Send a request with a JSON body
+    pub fn send_json(
+        &self,
+        request: ureq::Request,
+        data: impl Serialize,
+    ) -> Result<ureq::Response, ureq::Error> {
+        let data =
+            serde_json::to_value(data).map_err(|e| ureq::Error::from(std::io::Error::from(e)))?;
+        self.cached(request, Some(&data.to_string()), |request| {
+            self.retry(|| request.clone().send_json(&data))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_http() {
+        let url = "http://localhost:3000";
+        assert!(is_plain_http(url));
+        assert!(!is_plain_http("https://localhost:3000"));
+
+        let error = HttpClient::new(&HttpOptions::default(), url).err().unwrap();
+        assert!(error.to_string().contains("allow_insecure_http"));
+        let options = HttpOptions {
+            allow_insecure_http: true,
+            ..Default::default()
+        };
+        assert!(HttpClient::new(&options, url).is_ok());
+    }
+
+    #[test]
+    fn test_relative_ca_bundle() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let options = HttpOptions {
+            connection: ConnectionOptions {
+                ca_bundle: Some(PathBuf::from("certs/company.pem")),
+                ..Default::default()
+            },
+            workspace: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        let error = HttpClient::new(&options, "https://gitlab.company.com")
+            .err()
+            .unwrap();
+        let expected = temp_dir.path().join("certs/company.pem");
+        assert!(format!("{:#}", error).contains(&expected.display().to_string()));
+    }
+
+    #[test]
+    fn test_url_host() {
+        assert_eq!(url_host("https://api.github.com/graphql"), "api.github.com");
+        assert_eq!(
+            url_host("https://user@gitlab.company.com:8443"),
+            "gitlab.company.com"
+        );
+        assert_eq!(url_host("https://[::1]:3000/api"), "::1");
+    }
+
+    fn env(vars: &[(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        let vars = vars.to_vec();
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    #[test]
+    fn test_proxy_from_env() {
+        let url = "https://gitlab.company.com/api/v4";
+        let proxy = Some("http://proxy:3128".to_string());
+
+        assert_eq!(proxy_from_env(url, env(&[])), None);
+        assert_eq!(
+            proxy_from_env(url, env(&[("https_proxy", "http://proxy:3128")])),
+            proxy
+        );
+        assert_eq!(
+            proxy_from_env(url, env(&[("ALL_PROXY", "socks5://proxy:1080")])),
+            Some("socks5://proxy:1080".to_string())
+        );
+        for no_proxy in [
+            "company.com",
+            ".company.com",
+            "localhost, gitlab.company.com",
+            "*",
+        ] {
+            let vars = env(&[("HTTPS_PROXY", "http://proxy:3128"), ("NO_PROXY", no_proxy)]);
+            assert_eq!(proxy_from_env(url, vars), None, "{}", no_proxy);
+        }
+        let vars = env(&[
+            ("HTTPS_PROXY", "http://proxy:3128"),
+            ("NO_PROXY", "othercompany.com"),
+        ]);
+        assert_eq!(proxy_from_env(url, vars), proxy);
+    }
+
+    #[test]
+    fn test_proxy_from_env_by_scheme() {
+        let vars = [
+            ("HTTP_PROXY", "http://http-proxy:3128"),
+            ("https_proxy", "http://https-proxy:3128"),
+        ];
+        assert_eq!(
+            proxy_from_env("http://gitea.local:3000", env(&vars)),
+            Some("http://http-proxy:3128".to_string())
+        );
+        assert_eq!(
+            proxy_from_env("https://gitea.company.com", env(&vars)),
+            Some("http://https-proxy:3128".to_string())
+        );
+
+        // Each scheme falls back to ALL_PROXY, but not to the proxy of the other one
+        let vars = [
+            ("HTTPS_PROXY", "http://https-proxy:3128"),
+            ("ALL_PROXY", "socks5://proxy:1080"),
+        ];
+        assert_eq!(
+            proxy_from_env("http://gitea.local:3000", env(&vars)),
+            Some("socks5://proxy:1080".to_string())
+        );
+        assert_eq!(
+            proxy_from_env("http://gitea.local:3000", env(&vars[..1])),
+            None
+        );
+    }
+
+    fn status_error(response: &str) -> ureq::Error {
+        let response: ureq::Response = response.parse().unwrap();
+        ureq::Error::Status(response.status(), response)
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let bad_gateway = status_error("HTTP/1.1 502 Bad Gateway\r\n\r\n");
+        assert_eq!(
+            retry_delay(&bad_gateway, 0, now),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            retry_delay(&bad_gateway, 2, now),
+            Some(Duration::from_secs(4))
+        );
+
+        let retry_after = status_error("HTTP/1.1 429 Too Many Requests\r\nRetry-After: 7\r\n\r\n");
+        assert_eq!(
+            retry_delay(&retry_after, 0, now),
+            Some(Duration::from_secs(7))
+        );
+
+        let rate_limited = status_error(
+            "HTTP/1.1 403 Forbidden\r\nX-RateLimit-Remaining: 0\r\nX-RateLimit-Reset: 1700000020\r\n\r\n",
+        );
+        assert_eq!(
+            retry_delay(&rate_limited, 0, now),
+            Some(Duration::from_secs(21))
+        );
+
+        let reset_in_an_hour = status_error(
+            "HTTP/1.1 403 Forbidden\r\nX-RateLimit-Remaining: 0\r\nX-RateLimit-Reset: 1700003600\r\n\r\n",
+        );
+        assert_eq!(retry_delay(&reset_in_an_hour, 0, now), None);
+
+        let forbidden = status_error("HTTP/1.1 403 Forbidden\r\n\r\n");
+        assert_eq!(retry_delay(&forbidden, 0, now), None);
+        let not_found = status_error("HTTP/1.1 404 Not Found\r\n\r\n");
+        assert_eq!(retry_delay(&not_found, 0, now), None);
+    }
+}
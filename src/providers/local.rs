@@ -0,0 +1,271 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, PathTemplate, Provider,
+};
+use crate::repository::Repository;
+use crate::utils::expand_workspace_path;
+use anyhow::{anyhow, bail, Context};
+use console::style;
+use git2::Repository as Git2Repository;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Returns true if the directory is a bare repository, or the work tree of a repository
+fn is_repository(path: &Path) -> bool {
+    path.join(".git").exists()
+        || (path.join("HEAD").is_file()
+            && path.join("objects").is_dir()
+            && path.join("refs").is_dir())
+}
+
+/// Read the branch HEAD points to, if any
+fn head_branch(path: &Path) -> Option<String> {
+    let repo = Git2Repository::open(path).ok()?;
+    let head = repo.find_reference("HEAD").ok()?;
+    head.symbolic_target()
+        .and_then(|target| target.strip_prefix("refs/heads/"))
+        .map(String::from)
+}
+
+fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    if path.starts_with('/') {
+        format!("file://{}", path)
+    } else {
+        // Windows paths start with a drive letter
+        format!("file:///{}", path)
+    }
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add all repositories found in a local or mounted directory")]
+pub struct LocalProvider {
+    /// The directory to search for bare or non-bare repositories. Relative paths are relative
+    /// to the workspace.
+    pub name: PathBuf,
+
+    #[arg(long = "path", default_value = "local")]
+    /// Clone repositories to a specific base path
+    path: String,
+
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
+
+    #[arg(long = "file-url")]
+    #[serde(default)]
+    /// Use file:// URLs instead of plain paths
+    file_url: bool,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// is relative to the directory, without any `.git` suffix.
+    include: Vec<String>,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// is relative to the directory, without any `.git` suffix.
+    exclude: Vec<String>,
+
+    #[arg(skip)]
+    #[serde(skip)]
+    /// The workspace directory, which `name` is relative to
+    workspace: PathBuf,
+}
+
+impl fmt::Display for LocalProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Repositories from {} in directory {}",
+            style(self.name.display()).green(),
+            style(&self.path).green(),
+        )
+    }
+}
+
+impl LocalProvider {
+    /// The directory to search, with `~` expanded
+    fn root(&self) -> anyhow::Result<PathBuf> {
+        Ok(self.workspace.join(expand_workspace_path(&self.name)?))
+    }
+
+    /// The resolved directory to search. It can't contain the workspace, or the repositories
+    /// cloned in the workspace would be found again.
+    fn checked_root(&self) -> anyhow::Result<PathBuf> {
+        let root = self.root()?;
+        let root = root
+            .canonicalize()
+            .with_context(|| format!("Cannot resolve {}", root.display()))?;
+        // The workspace is only unknown in tests
+        if let Ok(workspace) = self.workspace.canonicalize() {
+            if workspace.starts_with(&root) {
+                bail!(
+                    "{} contains the workspace, search a directory outside of it",
+                    root.display()
+                );
+            }
+        }
+        Ok(root)
+    }
+}
+
+impl Provider for LocalProvider {
+    fn correctly_configured(&self) -> bool {
+        match self.checked_root() {
+            Ok(root) if root.is_dir() => true,
+            Ok(root) => {
+                println!(
+                    "{}",
+                    style(format!("Error: {} is not a directory", root.display())).red()
+                );
+                false
+            }
+            Err(e) => {
+                println!("{}", style(format!("Error: {:#}", e)).red());
+                false
+            }
+        }
+    }
+
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    fn set_workspace(&mut self, workspace: &Path) {
+        self.workspace = workspace.to_path_buf();
+    }
+
+    fn scope(&self) -> String {
+        self.name.display().to_string()
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        let root = self.checked_root()?;
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let mut repositories = Vec::new();
+        let mut it = WalkDir::new(&root).min_depth(1).into_iter();
+
+        loop {
+            let entry = match it.next() {
+                None => break,
+                Some(Err(err)) => {
+                    return Err(anyhow!("Error iterating through directory: {}", err))
+                }
+                Some(Ok(entry)) => entry,
+            };
+            if !entry.file_type().is_dir() || !is_repository(entry.path()) {
+                continue;
+            }
+            // Don't look for repositories inside of repositories
+            it.skip_current_dir();
+
+            let relative_path = entry.path().strip_prefix(&root)?;
+            let name = relative_path
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let name = name.strip_suffix(".git").unwrap_or(&name);
+            if !include_regex_set.is_match(name) || exclude_regex_set.is_match(name) {
+                continue;
+            }
+
+            repositories.push(Repository::from_full_name(
+                &self.path,
+                name,
+                if self.file_url {
+                    file_url(entry.path())
+                } else {
+                    entry.path().to_string_lossy().to_string()
+                },
+                head_branch(entry.path()),
+                None,
+            ));
+        }
+
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_fetch_repositories() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+
+        let bare = Git2Repository::init_bare(root.join("mirrors/api.git")).unwrap();
+        bare.set_head("refs/heads/main").unwrap();
+        Git2Repository::init(root.join("tools")).unwrap();
+        // Repositories nested in other repositories and plain directories are ignored
+        Git2Repository::init(root.join("tools/vendor/nested")).unwrap();
+        std::fs::create_dir_all(root.join("docs/drafts")).unwrap();
+
+        let provider = LocalProvider {
+            name: root.to_path_buf(),
+            path: "local".to_string(),
+            file_url: true,
+            ..Default::default()
+        };
+        let mut repositories = provider.fetch_repositories().unwrap();
+        repositories.sort();
+
+        assert_eq!(repositories.len(), 2);
+        assert!(repositories[0].name().ends_with("api"));
+        assert_eq!(repositories[0].branch, Some("main".to_string()));
+        assert!(repositories[1].name().ends_with("tools"));
+        assert!(repositories[1].branch.is_some());
+    }
+
+    #[test]
+    fn test_relative_to_workspace() {
+        let temp_dir = TempDir::new().unwrap();
+        let workspace = temp_dir.path();
+        Git2Repository::init(workspace.join("mirrors/api")).unwrap();
+
+        let mut provider = LocalProvider {
+            name: PathBuf::from("mirrors"),
+            path: "local".to_string(),
+            ..Default::default()
+        };
+        provider.set_workspace(workspace);
+        assert!(provider.correctly_configured());
+
+        let repositories = provider.fetch_repositories().unwrap();
+        assert_eq!(repositories.len(), 1);
+        assert_eq!(
+            Path::new(repositories[0].url()),
+            workspace.canonicalize().unwrap().join("mirrors/api")
+        );
+    }
+
+    #[test]
+    fn test_root_containing_workspace() {
+        let temp_dir = TempDir::new().unwrap();
+        let workspace = temp_dir.path().join("workspace");
+        Git2Repository::init(workspace.join("github/acme/api")).unwrap();
+
+        for name in [".", "..", "~/.."] {
+            let mut provider = LocalProvider {
+                name: PathBuf::from(name),
+                path: "local".to_string(),
+                ..Default::default()
+            };
+            provider.set_workspace(&workspace);
+            assert!(!provider.correctly_configured(), "{}", name);
+            assert!(provider.fetch_repositories().is_err(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use crate::providers::{check_repository_path, Provider};
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+
+/// A repository listed by hand, written to the lockfile as is
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
+pub struct ManualRepository {
+    pub path: String,
+    pub url: String,
+    #[serde(default)]
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub upstream: Option<String>,
+}
+
+impl From<ManualRepository> for Repository {
+    fn from(repo: ManualRepository) -> Self {
+        Repository::new(repo.path, repo.url, repo.branch, repo.upstream)
+    }
+}
+
+/// Repositories that are not hosted on a supported provider, like mirrors or vendor forks.
+///
+/// ```toml
+/// [[provider]]
+/// provider = "manual"
+/// repo = [
+///     { path = "vendor/libfoo", url = "https://example.com/libfoo.git", branch = "main" },
+/// ]
+/// ```
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct ManualProvider {
+    #[serde(rename = "repo", default)]
+    pub repos: Vec<ManualRepository>,
+}
+
+impl fmt::Display for ManualProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} manually listed repositories",
+            style(self.repos.len()).green()
+        )
+    }
+}
+
+impl ManualProvider {
+    fn check_repositories(&self) -> anyhow::Result<()> {
+        let mut paths = HashSet::new();
+        for repo in &self.repos {
+            if repo.url.is_empty() {
+                bail!("The repository at {:?} has no url", repo.path);
+            }
+            check_repository_path(&repo.path)
+                .with_context(|| format!("Invalid path for {}", repo.url))?;
+            if !paths.insert(&repo.path) {
+                bail!("The path {} is listed twice", repo.path);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Provider for ManualProvider {
+    fn correctly_configured(&self) -> bool {
+        if let Err(e) = self.check_repositories() {
+            println!("{}", style(format!("Error: {:#}", e)).red());
+            return false;
+        }
+        true
+    }
+
+    fn scope(&self) -> String {
+        // The repositories are listed in the configuration, there is nothing to tell apart
+        String::new()
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        // The repositories come from the configuration file, which `add` doesn't check
+        self.check_repositories()?;
+        Ok(self.repos.iter().cloned().map(Repository::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn provider(paths: &[&str]) -> ManualProvider {
+        ManualProvider {
+            repos: paths
+                .iter()
+                .map(|path| ManualRepository {
+                    path: path.to_string(),
+                    url: format!("https://example.com/{}.git", path),
+                    branch: None,
+                    upstream: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_invalid_repositories() {
+        assert!(provider(&["vendor/libfoo", "./libbar"])
+            .fetch_repositories()
+            .is_ok());
+        for paths in [
+            &["vendor/libfoo", "vendor/libfoo"][..],
+            &[""],
+            &["/srv/libfoo"],
+            &["../libfoo"],
+            &["vendor/../../libfoo"],
+            &["vendor\\..\\..\\libfoo"],
+        ] {
+            let provider = provider(paths);
+            assert!(provider.fetch_repositories().is_err(), "{:?}", paths);
+            assert!(!provider.correctly_configured());
+        }
+
+        let mut provider = provider(&["vendor/libfoo"]);
+        provider.repos[0].url = String::new();
+        assert!(provider.fetch_repositories().is_err());
+    }
+}
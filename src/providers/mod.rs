@@ -1,13 +1,42 @@
+mod azure_devops;
+mod bitbucket;
+mod bitbucket_server;
+mod cache;
+mod command;
+mod filter;
+mod gerrit;
 mod gitea;
 mod github;
 mod gitlab;
+mod http;
+mod local;
+mod manual;
+mod path_template;
+mod sourcehut;
+#[cfg(test)]
+mod test_server;
+mod token;
 
 use crate::repository::Repository;
 use anyhow::Context;
-pub use gitea::GiteaProvider;
+pub use azure_devops::AzureDevopsProvider;
+pub use bitbucket::BitbucketProvider;
+pub use bitbucket_server::BitbucketServerProvider;
+pub use cache::ResponseCache;
+pub use command::CommandProvider;
+pub use filter::{Archived, MetadataFilter, Period, Visibility};
+pub use gerrit::GerritProvider;
+pub use gitea::{ForgejoProvider, GiteaProvider};
 pub use github::GithubProvider;
 pub use gitlab::GitlabProvider;
+pub use http::{ConnectionOptions, HttpClient, HttpOptions};
+pub use local::LocalProvider;
+pub use manual::{ManualProvider, ManualRepository};
+pub use path_template::PathTemplate;
+pub use sourcehut::SourcehutProvider;
 use std::fmt;
+use std::path::{Component, Path};
+pub use token::TokenSource;
 
 pub static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
@@ -15,6 +44,63 @@ pub trait Provider: fmt::Display {
     /// Returns true if the provider should work, otherwise prints an error and return false
     fn correctly_configured(&self) -> bool;
     fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>>;
+    /// Tells the provider apart from the others of its kind, e.g. the organization it fetches
+    /// and the instance it's on. See [`crate::config::ProviderSource::id`].
+    fn scope(&self) -> String;
+    /// Returns the template used to lay out the repositories, if the provider has one
+    fn path_template(&self) -> Option<&PathTemplate> {
+        None
+    }
+    /// Returns how archived repositories are handled, for providers that know which are
+    fn archived(&self) -> Archived {
+        Archived::default()
+    }
+    /// Returns the options of the HTTP client, if the provider talks to an API
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        None
+    }
+    /// Returns where the token is read from, if the provider needs one
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        None
+    }
+    /// Sets the workspace directory, which relative paths of the provider are relative to
+    fn set_workspace(&mut self, _workspace: &Path) {}
+}
+
+/// The host and path of `url`, e.g. `gitlab.company.com` for `https://gitlab.company.com/`
+fn url_scope(url: &str) -> &str {
+    url.split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .trim_end_matches('/')
+}
+
+/// The scope of `name` on the instance at `url`, e.g. `gitlab.company.com/team`. The instance is
+/// left out if it's the default one of the provider.
+fn instance_scope(url: &str, default_url: &str, name: &str) -> String {
+    if url_scope(url) == url_scope(default_url) {
+        name.to_string()
+    } else {
+        format!("{}/{}", url_scope(url), name)
+    }
+}
+
+/// Checks that a repository path given by a configuration or a command stays in the workspace:
+/// it must be relative and cannot contain `..`
+fn check_repository_path(path: &str) -> anyhow::Result<()> {
+    if path.is_empty() {
+        anyhow::bail!("Repository paths cannot be empty");
+    }
+    let is_relative = Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    // Backslashes are separators on Windows, so check them on every platform
+    if !is_relative || path.starts_with('\\') || path.split(['/', '\\']).any(|s| s == "..") {
+        anyhow::bail!(
+            "Repository paths must be relative and cannot contain .., got {:?}",
+            path
+        );
+    }
+    Ok(())
 }
 
 pub fn create_exclude_regex_set(items: &Vec<String>) -> anyhow::Result<regex::RegexSet> {
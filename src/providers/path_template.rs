@@ -0,0 +1,305 @@
+use crate::providers::Archived;
+use crate::repository::Repository;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Variable {
+    Provider,
+    Path,
+    FullName,
+    Owner,
+    Name,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Helper {
+    Lower,
+    Flatten,
+}
+
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Part {
+    Literal(String),
+    Variable(Variable, Vec<Helper>),
+}
+
+/// Lays out the repositories of a provider, instead of cloning them to `{path}/{full_name}`.
+///
+/// Templates can use these variables:
+/// * `{provider}`: the type of provider, like `github`
+/// * `{path}`: the base path of the provider
+/// * `{full_name}`: the full name of the repository, like `owner/name` or `group/subgroup/name`
+/// * `{owner}` or `{group_path}`: the full name without the repository name
+/// * `{name}`: the repository name
+///
+/// Variables can be followed by helpers: `{owner|lower}` lowercases the owner, and
+/// `{group_path|flatten}` replaces slashes with dashes.
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[serde(try_from = "String", into = "String")]
+pub struct PathTemplate {
+    template: String,
+    parts: Vec<Part>,
+}
+
+fn parse_variable(template: &str, expression: &str) -> anyhow::Result<Part> {
+    let mut items = expression.split('|').map(str::trim);
+    let variable = match items.next().unwrap_or_default() {
+        "provider" => Variable::Provider,
+        "path" => Variable::Path,
+        "full_name" => Variable::FullName,
+        "owner" | "group_path" => Variable::Owner,
+        "name" => Variable::Name,
+        other => bail!(
+            "Unknown variable {{{}}} in path template {:?}",
+            other,
+            template
+        ),
+    };
+    let helpers = items
+        .map(|helper| match helper {
+            "lower" => Ok(Helper::Lower),
+            "flatten" => Ok(Helper::Flatten),
+            other => Err(anyhow!(
+                "Unknown helper {:?} in path template {:?}",
+                other,
+                template
+            )),
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Part::Variable(variable, helpers))
+}
+
+fn parse_literal(template: &str, literal: &str) -> anyhow::Result<Part> {
+    if literal.contains('}') {
+        bail!("Unexpected }} in path template {:?}", template);
+    }
+    if literal.split('/').any(|segment| segment == "..") {
+        bail!("Path templates cannot contain .., got {:?}", template);
+    }
+    Ok(Part::Literal(literal.to_string()))
+}
+
+impl FromStr for PathTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(template: &str) -> anyhow::Result<Self> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(parse_literal(template, &rest[..start])?);
+            }
+            let end = rest[start..]
+                .find('}')
+                .with_context(|| format!("Unclosed {{ in path template {:?}", template))?
+                + start;
+            parts.push(parse_variable(template, &rest[start + 1..end])?);
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(parse_literal(template, rest)?);
+        }
+
+        if template.starts_with('/') {
+            bail!("Path templates must be relative, got {:?}", template);
+        }
+        let has_name = parts
+            .iter()
+            .any(|part| matches!(part, Part::Variable(Variable::Name | Variable::FullName, _)));
+        if !has_name {
+            bail!(
+                "Path template {:?} must contain {{name}} or {{full_name}}",
+                template
+            );
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+            parts,
+        })
+    }
+}
+
+impl TryFrom<String> for PathTemplate {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<PathTemplate> for String {
+    fn from(template: PathTemplate) -> Self {
+        template.template
+    }
+}
+
+impl fmt::Display for PathTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+impl PathTemplate {
+    fn render(&self, provider: &str, base_path: &str, full_name: &str) -> String {
+        let (owner, name) = full_name.rsplit_once('/').unwrap_or(("", full_name));
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Variable(variable, helpers) => {
+                    let mut value = match variable {
+                        Variable::Provider => provider,
+                        Variable::Path => base_path,
+                        Variable::FullName => full_name,
+                        Variable::Owner => owner,
+                        Variable::Name => name,
+                    }
+                    .to_string();
+                    for helper in helpers {
+                        value = match helper {
+                            Helper::Lower => value.to_lowercase(),
+                            Helper::Flatten => value.replace('/', "-"),
+                        };
+                    }
+                    rendered.push_str(&value);
+                }
+            }
+        }
+        // Variables can be empty, like the owner of a top level Gerrit project
+        rendered
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Archived repositories are cloned separately by changing their base path, so the template
+    /// must contain `{path}` for them to be kept apart from the others
+    pub fn check_archived(&self, archived: Archived) -> anyhow::Result<()> {
+        let has_path = self
+            .parts
+            .iter()
+            .any(|part| matches!(part, Part::Variable(Variable::Path, _)));
+        if archived == Archived::Separate && !has_path {
+            bail!(
+                "Path template {:?} must contain {{path}} to clone archived repositories separately",
+                self.template
+            );
+        }
+        Ok(())
+    }
+
+    /// Move the repositories fetched by a provider to the paths given by the template
+    pub fn apply(
+        &self,
+        provider: &str,
+        mut repositories: Vec<Repository>,
+    ) -> anyhow::Result<Vec<Repository>> {
+        let mut paths = HashMap::new();
+        for repository in &mut repositories {
+            let Some(source) = repository.source().cloned() else {
+                continue;
+            };
+            let path = self.render(provider, &source.base_path, &source.full_name);
+            if let Some(other) = paths.insert(path.clone(), source.full_name.clone()) {
+                bail!(
+                    "Path template {:?} gives the same path {} to {} and {}",
+                    self.template,
+                    path,
+                    other,
+                    source.full_name
+                );
+            }
+            repository.set_path(path);
+        }
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, full_name: &str) -> String {
+        template
+            .parse::<PathTemplate>()
+            .unwrap()
+            .render("gitlab", "work", full_name)
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render("{path}/{full_name}", "platform/infra/Deploy"),
+            "work/platform/infra/Deploy"
+        );
+        assert_eq!(
+            render("{provider}/{owner}/{name}", "platform/infra/Deploy"),
+            "gitlab/platform/infra/Deploy"
+        );
+        assert_eq!(render("{name|lower}", "platform/infra/Deploy"), "deploy");
+        assert_eq!(
+            render(
+                "{path}/{group_path|flatten|lower}/{name}",
+                "Platform/infra/Deploy"
+            ),
+            "work/platform-infra/Deploy"
+        );
+        // Empty variables don't leave empty path segments
+        assert_eq!(render("{path}/{owner}/{name}", "tools"), "work/tools");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        for template in [
+            "{owner}",
+            "{path}/{unknown}/{name}",
+            "{name|upper}",
+            "{path}/{name",
+            "{path}}/{name}",
+            "../{name}",
+            "/srv/{name}",
+        ] {
+            assert!(
+                template.parse::<PathTemplate>().is_err(),
+                "{} should be invalid",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_archived() {
+        let template: PathTemplate = "{owner}/{name}".parse().unwrap();
+        assert!(template.check_archived(Archived::Skip).is_ok());
+        assert!(template.check_archived(Archived::Include).is_ok());
+        assert!(template.check_archived(Archived::Separate).is_err());
+
+        let template: PathTemplate = "{path}/{name}".parse().unwrap();
+        assert!(template.check_archived(Archived::Separate).is_ok());
+    }
+
+    #[test]
+    fn test_apply() {
+        let repositories = vec![
+            Repository::from_full_name("gitlab", "a/tools", "url-a".to_string(), None, None),
+            Repository::from_full_name("gitlab", "b/api", "url-b".to_string(), None, None),
+        ];
+        let template: PathTemplate = "{path}/{name}".parse().unwrap();
+        let repositories = template.apply("gitlab", repositories).unwrap();
+        // Paths are normalized to the platform separator
+        let expected = Repository::new("gitlab/tools".to_string(), String::new(), None, None);
+        assert_eq!(repositories[0].name(), expected.name());
+
+        let clashing = vec![
+            Repository::from_full_name("gitlab", "a/tools", "url-a".to_string(), None, None),
+            Repository::from_full_name("gitlab", "b/tools", "url-b".to_string(), None, None),
+        ];
+        assert!(template.apply("gitlab", clashing).is_err());
+    }
+}
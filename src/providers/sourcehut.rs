@@ -0,0 +1,353 @@
+use crate::providers::{
+    create_exclude_regex_set, create_include_regex_set, instance_scope, HttpClient, HttpOptions,
+    PathTemplate, Provider, TokenSource,
+};
+use crate::repository::Repository;
+use anyhow::{bail, Context};
+use console::style;
+use graphql_client::{GraphQLQuery, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+
+// See https://github.com/graphql-rust/graphql-client/blob/master/graphql_client/tests/custom_scalars.rs#L6
+type Cursor = String;
+
+#[derive(GraphQLQuery)]
+#[graphql(
+    schema_path = "src/providers/graphql/sourcehut/schema.graphqls",
+    query_path = "src/providers/graphql/sourcehut/projects.graphql",
+    response_derives = "Debug"
+)]
+pub struct Repositories;
+
+fn default_env_var() -> String {
+    String::from("SRHT_TOKEN")
+}
+
+static DEFAULT_SOURCEHUT_URL: &str = "https://git.sr.ht";
+
+fn public_sourcehut_url() -> String {
+    DEFAULT_SOURCEHUT_URL.to_string()
+}
+
+#[derive(Deserialize, Serialize, Default, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Parser)]
+#[serde(rename_all = "lowercase")]
+#[command(about = "Add a SourceHut user by name")]
+pub struct SourcehutProvider {
+    /// The name of the user to add, with or without the leading tilde
+    pub name: String,
+
+    #[arg(long = "path", default_value = "sourcehut")]
+    /// Clone repositories to a specific base path
+    path: String,
+
+    #[arg(long = "path-template")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Lay out repositories with a template instead of `{path}/{full_name}`, e.g. `{path}/{name}`
+    path_template: Option<PathTemplate>,
+
+    #[arg(long = "env-name", short = 'e', default_value = "SRHT_TOKEN")]
+    #[serde(default = "default_env_var")]
+    /// Environment variable containing the personal access token
+    env_var: String,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    token: TokenSource,
+
+    #[command(flatten)]
+    #[serde(flatten)]
+    http: HttpOptions,
+
+    #[arg(long = "include")]
+    #[serde(default)]
+    /// Only clone repositories that match these regular expressions. The repository name
+    /// includes the user name, prefixed with a tilde.
+    include: Vec<String>,
+
+    #[arg(long = "auth-http")]
+    #[serde(default)]
+    /// Use HTTP authentication instead of SSH
+    auth_http: bool,
+
+    #[arg(long = "exclude")]
+    #[serde(default)]
+    /// Don't clone repositories that match these regular expressions. The repository name
+    /// includes the user name, prefixed with a tilde.
+    exclude: Vec<String>,
+
+    #[arg(long = "url", default_value = DEFAULT_SOURCEHUT_URL)]
+    #[serde(default = "public_sourcehut_url")]
+    /// git.sr.ht instance URL
+    pub url: String,
+}
+
+impl fmt::Display for SourcehutProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SourceHut user {} at {} in directory {}, using the token from {}",
+            style(&self.name.to_lowercase()).green(),
+            style(&self.url).green(),
+            style(&self.path).green(),
+            style(self.token.describe(&self.env_var)).green(),
+        )
+    }
+}
+
+impl SourcehutProvider {
+    fn clone_url(&self, canonical_name: &str, repo: &str) -> String {
+        if self.auth_http {
+            format!("{}/{}/{}", self.url, canonical_name, repo)
+        } else {
+            let host = self
+                .url
+                .trim_start_matches("https://")
+                .trim_start_matches("http://");
+            format!("git@{}:{}/{}", host, canonical_name, repo)
+        }
+    }
+}
+
+impl Provider for SourcehutProvider {
+    fn correctly_configured(&self) -> bool {
+        if let Err(e) = self.token.resolve(&self.env_var, &self.url) {
+            println!("{}", style(format!("Error: {:#}", e)).red());
+            println!(
+                "Create a personal access token with the git.sr.ht REPOSITORIES scope here: {}",
+                style("https://meta.sr.ht/oauth2/personal-token").green()
+            );
+            println!(
+                "Then set a {} environment variable with the value",
+                style(&self.env_var).green()
+            );
+            return false;
+        }
+        if self.name.contains('/') {
+            println!(
+                "{}",
+                style("Error: Ensure that names do not contain forward slashes").red()
+            );
+            println!("You specified: {}", self.name);
+            return false;
+        }
+        true
+    }
+
+    fn path_template(&self) -> Option<&PathTemplate> {
+        self.path_template.as_ref()
+    }
+
+    fn http_options_mut(&mut self) -> Option<&mut HttpOptions> {
+        Some(&mut self.http)
+    }
+
+    fn token_mut(&mut self) -> Option<&mut TokenSource> {
+        Some(&mut self.token)
+    }
+
+    fn scope(&self) -> String {
+        instance_scope(
+            &self.url,
+            DEFAULT_SOURCEHUT_URL,
+            self.name.trim_start_matches('~'),
+        )
+    }
+
+    fn fetch_repositories(&self) -> anyhow::Result<Vec<Repository>> {
+        let sourcehut_token = self.token.resolve(&self.env_var, &self.url)?;
+
+        let mut repositories = vec![];
+        let mut cursor = None;
+
+        let include_regex_set = create_include_regex_set(&self.include)?;
+        let exclude_regex_set = create_exclude_regex_set(&self.exclude)?;
+
+        let client = HttpClient::new(&self.http, &self.url)?;
+
+        loop {
+            let q = Repositories::build_query(repositories::Variables {
+                username: self.name.trim_start_matches('~').to_string(),
+                cursor,
+            });
+            let res = client.send_json(
+                client
+                    .post(&format!("{}/query", self.url))
+                    .set("Authorization", &format!("Bearer {}", sourcehut_token)),
+                json!(&q),
+            )?;
+
+            let body = res.into_string()?;
+            let response_data: Response<repositories::ResponseData> = serde_json::from_str(&body)?;
+
+            if let Some(errors) = response_data.errors {
+                let combined_errors: Vec<_> = errors.into_iter().map(|e| e.message).collect();
+                bail!(
+                    "Received {} errors. Errors:\n{}",
+                    combined_errors.len(),
+                    combined_errors.join("\n")
+                );
+            }
+
+            let user = response_data
+                .data
+                .with_context(|| format!("Invalid response from SourceHut: {}", body))?
+                .user
+                .with_context(|| format!("SourceHut user {} could not be found", self.name))?;
+
+            repositories.extend(
+                user.repositories
+                    .results
+                    .into_iter()
+                    .map(|r| (format!("{}/{}", user.canonical_name, r.name), r))
+                    .filter(|(full_name, _)| include_regex_set.is_match(full_name))
+                    .filter(|(full_name, _)| !exclude_regex_set.is_match(full_name))
+                    .map(|(full_name, r)| {
+                        Repository::from_full_name(
+                            &self.path,
+                            &full_name,
+                            self.clone_url(&user.canonical_name, &r.name),
+                            r.head.map(|head| {
+                                head.name.trim_start_matches("refs/heads/").to_string()
+                            }),
+                            None,
+                        )
+                        .with_id(r.id)
+                    }),
+            );
+
+            cursor = user.repositories.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        Ok(repositories)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_server::{Response, TestServer};
+
+    fn repository(id: u64, name: &str, head: Option<&str>) -> serde_json::Value {
+        json!({"id": id, "name": name, "HEAD": head.map(|name| json!({"name": name}))})
+    }
+
+    fn server() -> TestServer {
+        TestServer::start(|request| {
+            if request.method != "POST" || request.target != "/query" {
+                return None;
+            }
+            let body: serde_json::Value = serde_json::from_str(&request.body).ok()?;
+            let variables = &body["variables"];
+            if variables["username"] != "alice" {
+                return Some(Response::json(json!({"data": {"user": null}})));
+            }
+            let repositories = match variables["cursor"].as_str() {
+                None => json!({
+                    "cursor": "page-2",
+                    "results": [
+                        repository(1, "dotfiles", Some("refs/heads/main")),
+                        repository(2, "empty", None),
+                    ],
+                }),
+                Some("page-2") => json!({
+                    "cursor": null,
+                    "results": [repository(3, "scratch", Some("refs/heads/trunk"))],
+                }),
+                Some(_) => return None,
+            };
+            Some(Response::json(json!({
+                "data": {"user": {"canonicalName": "~alice", "repositories": repositories}}
+            })))
+        })
+    }
+
+    fn provider(server: &TestServer, name: &str) -> SourcehutProvider {
+        SourcehutProvider {
+            name: name.to_string(),
+            path: "sourcehut".to_string(),
+            url: server.url.clone(),
+            token: TokenSource {
+                token_command: Some("echo secret".to_string()),
+                ..Default::default()
+            },
+            http: HttpOptions {
+                allow_insecure_http: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fetch_repositories() {
+        let server = server();
+        let repositories = provider(&server, "~alice").fetch_repositories().unwrap();
+
+        let summary: Vec<_> = repositories
+            .iter()
+            .map(|r| {
+                (
+                    r.name().as_str(),
+                    r.url(),
+                    r.branch.as_deref(),
+                    r.id.as_deref(),
+                )
+            })
+            .collect();
+        let host = server.url.trim_start_matches("http://");
+        assert_eq!(
+            summary,
+            [
+                (
+                    "sourcehut/~alice/dotfiles",
+                    format!("git@{}:~alice/dotfiles", host).as_str(),
+                    Some("main"),
+                    Some("1")
+                ),
+                (
+                    "sourcehut/~alice/empty",
+                    format!("git@{}:~alice/empty", host).as_str(),
+                    None,
+                    Some("2")
+                ),
+                (
+                    "sourcehut/~alice/scratch",
+                    format!("git@{}:~alice/scratch", host).as_str(),
+                    Some("trunk"),
+                    Some("3")
+                ),
+            ]
+        );
+        assert!(server
+            .requests()
+            .iter()
+            .all(|request| request.header("Authorization") == Some("Bearer secret")));
+    }
+
+    #[test]
+    fn test_fetch_repositories_filters() {
+        let server = server();
+        let mut provider = provider(&server, "alice");
+        provider.auth_http = true;
+        provider.exclude = vec!["empty$".to_string(), "scratch$".to_string()];
+        let repositories = provider.fetch_repositories().unwrap();
+
+        assert_eq!(repositories.len(), 1);
+        assert_eq!(
+            repositories[0].url(),
+            format!("{}/~alice/dotfiles", server.url)
+        );
+    }
+
+    #[test]
+    fn test_unknown_user() {
+        let server = server();
+        let error = provider(&server, "bob").fetch_repositories().unwrap_err();
+        assert_eq!(error.to_string(), "SourceHut user bob could not be found");
+    }
+}
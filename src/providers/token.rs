@@ -0,0 +1,313 @@
+use crate::utils::expand_workspace_path;
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+// Providers are fetched in parallel, and token commands share the terminal to ask for passphrases
+static TOKEN_COMMAND_LOCK: Mutex<()> = Mutex::new(());
+
+/// Where the token of a provider is read from. The environment variable of the provider is used
+/// when none of these are set.
+#[derive(
+    Deserialize, Serialize, Default, Clone, Debug, Eq, Ord, PartialEq, PartialOrd, clap::Args,
+)]
+pub struct TokenSource {
+    #[arg(long = "token-command")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Read the token from the first line printed by this shell command, e.g. `pass show github`
+    pub token_command: Option<String>,
+
+    #[arg(long = "token-file")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    /// Read the token from this file
+    pub token_file: Option<PathBuf>,
+
+    #[arg(long = "credential-helper")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    /// Read the token from the git credential helper, using `git credential fill`
+    pub credential_helper: bool,
+
+    #[arg(skip)]
+    #[serde(skip)]
+    /// The workspace directory, which a relative `token_file` is relative to
+    pub workspace: PathBuf,
+}
+
+/// Split a URL into the protocol and host given to `git credential fill`
+fn credential_protocol_and_host(url: &str) -> anyhow::Result<(&str, &str)> {
+    let (protocol, rest) = url
+        .split_once("://")
+        .with_context(|| format!("Invalid URL {}", url))?;
+    let host = rest.split('/').next().unwrap_or_default();
+    // API hosts like api.github.com or api.bitbucket.org share the credentials of the main host
+    let host = host.strip_prefix("api.").unwrap_or(host);
+    Ok((protocol, host))
+}
+
+/// Parse the `key=value` lines printed by `git credential fill` into a username and password
+fn parse_credential(output: &str) -> (Option<&str>, Option<&str>) {
+    let mut username = None;
+    let mut password = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("username", value)) => username = Some(value),
+            Some(("password", value)) => password = Some(value),
+            _ => {}
+        }
+    }
+    (username, password)
+}
+
+fn run_token_command(command: &str) -> anyhow::Result<String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    // Password managers may need to ask for a passphrase, so only one command runs at a time
+    let _lock = TOKEN_COMMAND_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let output = shell
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Error running token command `{}`", command))?;
+    if !output.status.success() {
+        bail!(
+            "Token command `{}` exited with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .with_context(|| format!("Token command `{}` printed invalid UTF-8", command))?;
+    Ok(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
+
+fn read_token_file(path: &Path, workspace: &Path) -> anyhow::Result<String> {
+    let path = workspace.join(expand_workspace_path(path)?);
+    let contents = fs::read_to_string(&path)
+        .with_context(|| format!("Cannot read token file {}", path.display()))?;
+    Ok(contents.trim().to_string())
+}
+
+fn git_credential_fill(url: &str, with_username: bool) -> anyhow::Result<String> {
+    let (protocol, host) = credential_protocol_and_host(url)?;
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        // Fail instead of prompting for a username and password in the middle of a lock
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Error running git credential fill")?;
+    child
+        .stdin
+        .take()
+        .context("Error writing to git credential fill")?
+        .write_all(format!("protocol={}\nhost={}\n\n", protocol, host).as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "git credential fill found no credentials for {}: {}",
+            host,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match parse_credential(&stdout) {
+        (Some(username), Some(password)) if with_username => {
+            Ok(format!("{}:{}", username, password))
+        }
+        (_, Some(password)) => Ok(password.to_string()),
+        _ => Err(anyhow!(
+            "git credential fill returned no password for {}",
+            host
+        )),
+    }
+}
+
+impl TokenSource {
+    fn resolve_with(
+        &self,
+        env_var: &str,
+        url: &str,
+        with_username: bool,
+    ) -> anyhow::Result<String> {
+        let token = match (
+            &self.token_command,
+            &self.token_file,
+            self.credential_helper,
+        ) {
+            (None, None, false) => env::var(env_var)
+                .map_err(|_| anyhow!("{} environment variable is not defined", env_var))?,
+            (Some(command), None, false) => run_token_command(command)?,
+            (None, Some(path), false) => read_token_file(path, &self.workspace)?,
+            (None, None, true) => git_credential_fill(url, with_username)?,
+            _ => bail!("Only one of token_command, token_file and credential_helper can be set"),
+        };
+        if token.is_empty() {
+            bail!("The token read from {} is empty", self.describe(env_var));
+        }
+        Ok(token)
+    }
+
+    /// Read the token of a provider. `url` is the address of the provider, used to look up
+    /// credentials with the git credential helper.
+    pub fn resolve(&self, env_var: &str, url: &str) -> anyhow::Result<String> {
+        self.resolve_with(env_var, url, false)
+    }
+
+    /// Like [`TokenSource::resolve`], for providers that use `username:password` credentials.
+    /// Both are read from the git credential helper.
+    pub fn resolve_username_password(&self, env_var: &str, url: &str) -> anyhow::Result<String> {
+        self.resolve_with(env_var, url, true)
+    }
+
+    /// Returns true if the token is read from somewhere else than the environment variable
+    pub fn is_configured(&self) -> bool {
+        self.token_command.is_some() || self.token_file.is_some() || self.credential_helper
+    }
+
+    /// Describes where the token is read from
+    pub fn describe(&self, env_var: &str) -> String {
+        if let Some(command) = &self.token_command {
+            format!("the output of `{}`", command)
+        } else if let Some(path) = &self.token_file {
+            format!("the file {}", path.display())
+        } else if self.credential_helper {
+            "the git credential helper".to_string()
+        } else {
+            env_var.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_resolve() {
+        let temp_dir = TempDir::new().unwrap();
+        let token_file = temp_dir.path().join("token");
+        fs::write(&token_file, "file-token\n").unwrap();
+
+        let from_file = TokenSource {
+            token_file: Some(token_file.clone()),
+            ..Default::default()
+        };
+        assert_eq!(
+            from_file.resolve("UNUSED", "https://gitlab.com").unwrap(),
+            "file-token"
+        );
+
+        let missing_env = TokenSource::default();
+        let error = missing_env
+            .resolve("GIT_WORKSPACE_TEST_UNDEFINED_TOKEN", "https://gitlab.com")
+            .unwrap_err();
+        assert!(error.to_string().contains("is not defined"));
+
+        let conflicting = TokenSource {
+            token_file: Some(token_file),
+            credential_helper: true,
+            ..Default::default()
+        };
+        assert!(conflicting.resolve("UNUSED", "https://gitlab.com").is_err());
+    }
+
+    #[test]
+    fn test_resolve_relative_token_file() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("secrets")).unwrap();
+        fs::write(temp_dir.path().join("secrets/gitlab"), "file-token\n").unwrap();
+
+        let source = TokenSource {
+            token_file: Some(PathBuf::from("secrets/gitlab")),
+            workspace: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        };
+        assert_eq!(
+            source.resolve("UNUSED", "https://gitlab.com").unwrap(),
+            "file-token"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_token_commands_run_one_at_a_time() {
+        let temp_dir = TempDir::new().unwrap();
+        // mkdir fails if another command holds the directory
+        let source = TokenSource {
+            token_command: Some(format!(
+                "mkdir {0} && sleep 0.1 && rmdir {0} && echo token",
+                temp_dir.path().join("running").display()
+            )),
+            ..Default::default()
+        };
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| source.resolve("UNUSED", "https://gitlab.com")))
+                .collect();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap().unwrap(), "token");
+            }
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_resolve_command() {
+        let source = TokenSource {
+            token_command: Some("printf 'command-token\\nurl: example.com\\n'".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            source.resolve("UNUSED", "https://gitlab.com").unwrap(),
+            "command-token"
+        );
+
+        let failing = TokenSource {
+            token_command: Some("echo locked >&2; exit 1".to_string()),
+            ..Default::default()
+        };
+        let error = failing.resolve("UNUSED", "https://gitlab.com").unwrap_err();
+        assert!(error.to_string().contains("locked"));
+    }
+
+    #[test]
+    fn test_credential_protocol_and_host() {
+        assert_eq!(
+            credential_protocol_and_host("https://api.github.com/graphql").unwrap(),
+            ("https", "github.com")
+        );
+        assert_eq!(
+            credential_protocol_and_host("https://gitlab.company.com").unwrap(),
+            ("https", "gitlab.company.com")
+        );
+        assert!(credential_protocol_and_host("gitlab.company.com").is_err());
+    }
+
+    #[test]
+    fn test_parse_credential() {
+        assert_eq!(
+            parse_credential("protocol=https\nhost=github.com\nusername=me\npassword=secret\n"),
+            (Some("me"), Some("secret"))
+        );
+        assert_eq!(parse_credential("protocol=https\n"), (None, None));
+    }
+}
@@ -8,6 +8,13 @@ use std::io::{BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 
+/// Where a provider found a repository, used to lay it out with a path template
+#[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
+pub struct RepositorySource {
+    pub base_path: String,
+    pub full_name: String,
+}
+
 // Eq, Ord and friends are needed to order the list of repositories
 #[derive(Deserialize, Serialize, Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Repository {
@@ -15,6 +22,30 @@ pub struct Repository {
     url: String,
     pub upstream: Option<String>,
     pub branch: Option<String>,
+    /// The provider that found the repository, see [`crate::config::ProviderSource::id`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    /// The identifier of the repository in its provider, which doesn't change when it's renamed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Set when the provider failed while locking, and the repository was kept from the
+    /// previous lockfile
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    // Only known while locking, this isn't written to the lockfile
+    #[serde(skip)]
+    source: Option<RepositorySource>,
+}
+
+// We have to normalize repository names here. On windows if you do `path.join(self.name())`
+// it will cause issues if the name contains a forward slash. So here we just normalize it
+// to the path separator on the system.
+fn normalize_path(path: String) -> String {
+    if cfg!(windows) {
+        path.replace('/', std::path::MAIN_SEPARATOR.to_string().as_str())
+    } else {
+        path
+    }
 }
 
 impl Repository {
@@ -24,23 +55,56 @@ impl Repository {
         branch: Option<String>,
         upstream: Option<String>,
     ) -> Repository {
-        // We have to normalize repository names here. On windows if you do `path.join(self.name())`
-        // it will cause issues if the name contains a forward slash. So here we just normalize it
-        // to the path separator on the system.
-        let norm_path = if cfg!(windows) {
-            path.replace('/', std::path::MAIN_SEPARATOR.to_string().as_str())
-        } else {
-            path
-        };
-
         Repository {
-            path: norm_path,
+            path: normalize_path(path),
             url,
             branch,
             upstream,
+            provider: None,
+            id: None,
+            stale: false,
+            source: None,
+        }
+    }
+
+    /// Create a repository cloned to `base_path/full_name`, remembering both parts so that it can
+    /// be moved with a path template
+    pub fn from_full_name(
+        base_path: &str,
+        full_name: &str,
+        url: String,
+        branch: Option<String>,
+        upstream: Option<String>,
+    ) -> Repository {
+        Repository {
+            source: Some(RepositorySource {
+                base_path: base_path.to_string(),
+                full_name: full_name.to_string(),
+            }),
+            ..Repository::new(
+                format!("{}/{}", base_path, full_name),
+                url,
+                branch,
+                upstream,
+            )
+        }
+    }
+
+    pub fn with_id(self, id: impl ToString) -> Repository {
+        Repository {
+            id: Some(id.to_string()),
+            ..self
         }
     }
 
+    pub fn source(&self) -> Option<&RepositorySource> {
+        self.source.as_ref()
+    }
+
+    pub fn set_path(&mut self, path: String) {
+        self.path = normalize_path(path);
+    }
+
     pub fn set_upstream(&self, root: &Path) -> anyhow::Result<()> {
         let upstream = match &self.upstream {
             Some(upstream) => upstream,
@@ -180,6 +244,9 @@ impl Repository {
     pub fn name(&self) -> &String {
         &self.path
     }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
     pub fn get_path(&self, root: &Path) -> anyhow::Result<PathBuf> {
         let joined = root.join(self.name());
         joined
@@ -10,10 +10,9 @@ fn build_prompt_text(
     show_default: bool,
     default: Option<&str>,
 ) -> String {
-    let prompt_text = if default.is_some() && show_default {
-        format!("{} [{}]", text, default.unwrap())
-    } else {
-        text.to_string()
+    let prompt_text = match default {
+        Some(default) if show_default => format!("{} [{}]", text, default),
+        _ => text.to_string(),
     };
     prompt_text + suffix
 }
@@ -56,11 +55,12 @@ pub fn confirm(text: &str, default: bool, prompt_suffix: &str, show_default: boo
 
 // Convert our workspace path to a PathBuf. We cannot use the value given directly as
 // it could contain a tilde, so we run `expanduser` on it _if_ we are on a Unix platform.
-// On Windows this isn't supported.
+// On Windows this isn't supported. Paths in the configuration, like token files, are expanded
+// the same way.
 #[cfg(unix)]
 pub fn expand_workspace_path(path: &Path) -> anyhow::Result<PathBuf> {
     expanduser::expanduser(path.to_string_lossy())
-        .with_context(|| "Error expanding git workspace path")
+        .with_context(|| format!("Error expanding path {}", path.display()))
 }
 
 #[cfg(not(unix))]
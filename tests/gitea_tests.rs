@@ -31,13 +31,13 @@ fn cleanup() {
 }
 
 fn update_command(workspace_path: &Path) {
-    lock(workspace_path).unwrap();
+    lock(workspace_path, false).unwrap();
     update(workspace_path, 8).unwrap();
 }
 
 fn execute_command(workspace_path: &Path, cmd: &str, args_raw: &str) {
     let args: Vec<String> = args_raw.split(" ").map(String::from).collect();
-    execute_cmd(workspace_path, 8, cmd.to_string(), args).unwrap();
+    execute_cmd(workspace_path, 8, None, cmd.to_string(), args).unwrap();
 }
 
 #[rstest]
@@ -86,7 +86,7 @@ fn test_archive_command(gitea_container: &GiteaContainer) {
 
     // Test archive command
     gitea_container.delete_repos(&org_name, ["repo2"]);
-    archive(workspace, true).unwrap();
+    archive(workspace, true, false).unwrap();
 
     // Check if .git/config exists for repo2 is in the .archive directory
     let repo2 = workspace.join(format!(".archive/{}/repo2/.git/config", org_name));
@@ -107,7 +107,7 @@ fn test_fetch_and_run_commands(gitea_container: &GiteaContainer) {
     let content = "Hello Orf".to_string();
     let commit = GiteaCommit::new("main", "chore: initial commit", "Hello Orf");
     gitea_container.commit_to_repo(&org_name, "repo1", "README.md", &commit);
-    fetch(workspace, 8).unwrap();
+    fetch(workspace, 8, None).unwrap();
     execute_command(workspace, "git", "merge origin/main");
 
     let org_dir = workspace.join(&org_name).join("repo1");
@@ -0,0 +1,120 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use tempfile::TempDir;
+
+// The provider can't be reached: its token is not defined and its host doesn't exist
+const CONFIG: &str = r#"[[provider]]
+provider = "gitea"
+name = "acme"
+url = "https://gitea.invalid"
+path = "gitea"
+env_var = "GIT_WORKSPACE_TEST_UNDEFINED_TOKEN"
+"#;
+
+fn git_workspace(workspace: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_git-workspace"))
+        .arg("--workspace")
+        .arg(workspace)
+        .args(args)
+        .env_remove("GIT_WORKSPACE_TEST_UNDEFINED_TOKEN")
+        .output()
+        .unwrap()
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let output = Command::new("git")
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Create a workspace with a lockfile listing one repository, cloned from a local directory
+fn workspace(temp_dir: &TempDir) -> std::path::PathBuf {
+    let upstream = temp_dir.path().join("upstream/api");
+    fs::create_dir_all(&upstream).unwrap();
+    git(&upstream, &["init", "--initial-branch=main"]);
+    git(
+        &upstream,
+        &["commit", "--allow-empty", "-m", "Initial commit"],
+    );
+
+    let workspace = temp_dir.path().join("workspace");
+    fs::create_dir_all(&workspace).unwrap();
+    fs::write(workspace.join("workspace.toml"), CONFIG).unwrap();
+    fs::write(
+        workspace.join("workspace-lock.toml"),
+        format!(
+            "[[repo]]\npath = \"gitea/acme/api\"\nurl = '{}'\nbranch = \"main\"\n",
+            upstream.display()
+        ),
+    )
+    .unwrap();
+    workspace
+}
+
+#[test]
+fn test_update_offline() {
+    let temp_dir = TempDir::new().unwrap();
+    let workspace = workspace(&temp_dir);
+
+    // Without --offline, the provider is needed to lock the workspace
+    let output = git_workspace(&workspace, &["update"]);
+    assert!(!output.status.success());
+    assert!(!workspace.join("gitea/acme/api").exists());
+
+    let output = git_workspace(&workspace, &["update", "--offline"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(workspace.join("gitea/acme/api/.git").is_dir());
+}
+
+#[test]
+fn test_lock_offline() {
+    let temp_dir = TempDir::new().unwrap();
+    let workspace = workspace(&temp_dir);
+    let lockfile = fs::read_to_string(workspace.join("workspace-lock.toml")).unwrap();
+
+    let output = git_workspace(&workspace, &["lock", "--offline"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("The lockfile cannot be updated offline")
+    );
+    assert_eq!(
+        fs::read_to_string(workspace.join("workspace-lock.toml")).unwrap(),
+        lockfile
+    );
+}
+
+#[test]
+fn test_archive_offline() {
+    let temp_dir = TempDir::new().unwrap();
+    let workspace = workspace(&temp_dir);
+    // A repository that isn't in the lockfile anymore
+    let removed = workspace.join("gitea/acme/removed");
+    fs::create_dir_all(&removed).unwrap();
+    git(&removed, &["init"]);
+
+    let output = git_workspace(&workspace, &["update", "--offline"]);
+    assert!(output.status.success());
+    let output = git_workspace(&workspace, &["archive", "--offline", "--force"]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    assert!(workspace.join("gitea/acme/api/.git").is_dir());
+    assert!(!removed.exists());
+    assert!(workspace.join(".archive/gitea/acme/removed/.git").is_dir());
+}